@@ -1,8 +1,9 @@
 use advent_of_code_2020::Input;
-use lazy_static::lazy_static;
-use regex::Regex;
-use std::error;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::iter::FromIterator;
 use std::str::FromStr;
+use std::{env, error, fmt};
 use thiserror::Error;
 
 /// Split string into two at the given delimiter
@@ -11,9 +12,249 @@ fn split1(s: &str, delimiter: char) -> Option<(&str, &str)> {
     Some((s[..pos].trim(), s[pos + 1..].trim()))
 }
 
-#[derive(Debug, Error)]
-#[error("Invalid passport")]
-struct InvalidPassport;
+/// Passport field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Field {
+    Byr,
+    Iyr,
+    Eyr,
+    Hgt,
+    Hcl,
+    Ecl,
+    Pid,
+    Cid,
+}
+
+impl Field {
+    const ALL: [Field; 8] = [
+        Field::Byr,
+        Field::Iyr,
+        Field::Eyr,
+        Field::Hgt,
+        Field::Hcl,
+        Field::Ecl,
+        Field::Pid,
+        Field::Cid,
+    ];
+
+    /// Field with the given key
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|field| field.key() == key)
+    }
+
+    /// Key of this field
+    const fn key(self) -> &'static str {
+        match self {
+            Field::Byr => "byr",
+            Field::Iyr => "iyr",
+            Field::Eyr => "eyr",
+            Field::Hgt => "hgt",
+            Field::Hcl => "hcl",
+            Field::Ecl => "ecl",
+            Field::Pid => "pid",
+            Field::Cid => "cid",
+        }
+    }
+
+    /// Whether a passport needs to have this field
+    fn is_required(self) -> bool {
+        self != Field::Cid
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.key())
+    }
+}
+
+/// Field value that breaks a validation rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct InvalidField {
+    field: Field,
+    value: String,
+    rule: &'static str,
+}
+
+/// Key that appears more than once, with every value given for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DuplicateField {
+    field: Field,
+    values: Vec<String>,
+}
+
+impl DuplicateField {
+    /// Whether the repeated key has differing values
+    fn is_conflicting(&self) -> bool {
+        self.values.iter().any(|value| *value != self.values[0])
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Error)]
+struct InvalidPassport {
+    missing: Vec<Field>,
+    invalid: Vec<InvalidField>,
+    unknown: Vec<String>,
+    duplicate: Vec<DuplicateField>,
+    malformed: Vec<String>,
+}
+
+impl InvalidPassport {
+    /// Whether no problem was found at all
+    fn is_empty(&self) -> bool {
+        self.missing.is_empty()
+            && self.invalid.is_empty()
+            && self.unknown.is_empty()
+            && self.duplicate.is_empty()
+            && self.malformed.is_empty()
+    }
+
+    /// Human readable descriptions of every problem found
+    fn reasons(&self) -> Vec<String> {
+        let missing = self.missing.iter().map(|f| format!("missing `{}`", f));
+        let invalid = self
+            .invalid
+            .iter()
+            .map(|i| format!("invalid `{}` value `{}` ({})", i.field, i.value, i.rule));
+        let unknown = self.unknown.iter().map(|k| format!("unknown key `{}`", k));
+        let duplicate = self.duplicate.iter().map(|d| {
+            if d.is_conflicting() {
+                format!(
+                    "conflicting `{}` values `{}`",
+                    d.field,
+                    d.values.join("`, `")
+                )
+            } else {
+                format!("duplicate `{}`", d.field)
+            }
+        });
+        let malformed = self.malformed.iter().map(|s| format!("malformed `{}`", s));
+        missing
+            .chain(invalid)
+            .chain(unknown)
+            .chain(duplicate)
+            .chain(malformed)
+            .collect()
+    }
+}
+
+impl fmt::Display for InvalidPassport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid passport: {}", self.reasons().join(", "))
+    }
+}
+
+/// How strictly to treat the key/value pairs of a batch block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParseMode {
+    /// Later keys silently overwrite earlier ones, values are taken as-is
+    Lenient,
+    /// Repeated keys, empty values and pairs with more than one colon are rejected
+    Strict,
+}
+
+/// Passport fields of a batch block, before applying any validation policy
+#[derive(Debug, Default)]
+struct PassportFields {
+    values: BTreeMap<Field, String>,
+    problems: InvalidPassport,
+}
+
+impl PassportFields {
+    /// Collect key/value pairs, recording problems according to the given mode
+    fn parse(s: &str, mode: ParseMode) -> Self {
+        let mut fields = Self::default();
+        // First value of every key in strict mode, including empty ones
+        let mut first: BTreeMap<Field, &str> = BTreeMap::new();
+        for set in s.split_whitespace() {
+            let (key, value) = match split1(set, ':') {
+                Some((_key, value)) if mode == ParseMode::Strict && value.contains(':') => {
+                    fields.problems.malformed.push(set.to_string());
+                    continue;
+                }
+                Some(kv) => kv,
+                None => {
+                    fields.problems.malformed.push(set.to_string());
+                    continue;
+                }
+            };
+            let field = match Field::from_key(key) {
+                Some(field) => field,
+                None => {
+                    fields.problems.unknown.push(key.to_string());
+                    continue;
+                }
+            };
+            if mode == ParseMode::Lenient {
+                fields.values.insert(field, value.to_string());
+                continue;
+            }
+            let repeated = match first.get(&field) {
+                Some(previous) => {
+                    let duplicate = &mut fields.problems.duplicate;
+                    match duplicate.iter_mut().find(|d| d.field == field) {
+                        Some(d) => d.values.push(value.to_string()),
+                        None => duplicate.push(DuplicateField {
+                            field,
+                            values: vec![previous.to_string(), value.to_string()],
+                        }),
+                    }
+                    true
+                }
+                None => {
+                    first.insert(field, value);
+                    false
+                }
+            };
+            if value.is_empty() {
+                fields.problems.invalid.push(InvalidField {
+                    field,
+                    value: value.to_string(),
+                    rule: "non-empty value",
+                });
+            } else if !repeated {
+                fields.values.insert(field, value.to_string());
+            }
+        }
+        fields
+    }
+
+    /// Problems found while collecting fields, plus any missing required fields
+    fn problems(&self) -> InvalidPassport {
+        let mut problems = self.problems.clone();
+        problems.missing = Field::ALL
+            .iter()
+            .copied()
+            .filter(|field| field.is_required() && !self.values.contains_key(field))
+            .filter(|field| !self.problems.invalid.iter().any(|i| i.field == *field))
+            .collect();
+        problems
+    }
+
+    /// Value of the given field, if present
+    fn get(&self, field: Field) -> Option<&str> {
+        self.values.get(&field).map(String::as_str)
+    }
+
+    /// Parsed value of the given field, recording it as invalid if it breaks its rule
+    fn check<T>(
+        &self,
+        field: Field,
+        parse: impl FnOnce(&str) -> Result<T, InvalidValue>,
+        problems: &mut InvalidPassport,
+    ) -> Option<T> {
+        let value = self.get(field)?;
+        parse(value)
+            .map_err(|InvalidValue(rule)| {
+                problems.invalid.push(InvalidField {
+                    field,
+                    value: value.to_string(),
+                    rule,
+                })
+            })
+            .ok()
+    }
+}
 
 #[derive(Debug)]
 struct Passport {
@@ -31,45 +272,219 @@ impl FromStr for Passport {
     type Err = InvalidPassport;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (mut byr, mut iyr, mut eyr, mut hgt, mut hcl, mut ecl, mut pid, mut cid) =
-            (None, None, None, None, None, None, None, None);
-        for set in s.split_whitespace() {
-            let (key, value) = split1(set, ':').ok_or(InvalidPassport)?;
-            match key {
-                "byr" => byr = Some(value.to_string()),
-                "iyr" => iyr = Some(value.to_string()),
-                "eyr" => eyr = Some(value.to_string()),
-                "hgt" => hgt = Some(value.to_string()),
-                "hcl" => hcl = Some(value.to_string()),
-                "ecl" => ecl = Some(value.to_string()),
-                "pid" => pid = Some(value.to_string()),
-                "cid" => cid = Some(value.to_string()),
-                _ => return Err(InvalidPassport),
-            }
+        Self::from_fields(&PassportFields::parse(s, ParseMode::Lenient))
+    }
+}
+
+impl Passport {
+    fn from_fields(fields: &PassportFields) -> Result<Self, InvalidPassport> {
+        let problems = fields.problems();
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+        let value = |field| fields.get(field).unwrap_or_default().to_string();
+        Ok(Self {
+            byr: value(Field::Byr),
+            iyr: value(Field::Iyr),
+            eyr: value(Field::Eyr),
+            hgt: value(Field::Hgt),
+            hcl: value(Field::Hcl),
+            ecl: value(Field::Ecl),
+            pid: value(Field::Pid),
+            cid: fields.get(Field::Cid).map(String::from),
+        })
+    }
+}
+
+/// Rule that a field value breaks
+#[derive(Debug, Error)]
+#[error("{0}")]
+struct InvalidValue(&'static str);
+
+/// Parse a four digit year in the given range
+fn parse_year(s: &str, min: u16, max: u16, rule: &'static str) -> Result<u16, InvalidValue> {
+    Some(s)
+        .filter(|s| s.len() == 4 && s.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|s| s.parse().ok())
+        .filter(|year| (min..=max).contains(year))
+        .ok_or(InvalidValue(rule))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Height {
+    Cm(u16),
+    In(u16),
+}
+
+impl FromStr for Height {
+    type Err = InvalidValue;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const RULE: &str = "150-193cm or 59-76in";
+        let number = |s: &str| {
+            Some(s)
+                .filter(|s| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|s| s.parse().ok())
+        };
+        let height = if let Some(cm) = s.strip_suffix("cm") {
+            number(cm)
+                .filter(|cm| (150..=193).contains(cm))
+                .map(Height::Cm)
+        } else if let Some(inches) = s.strip_suffix("in") {
+            number(inches)
+                .filter(|inches| (59..=76).contains(inches))
+                .map(Height::In)
+        } else {
+            None
+        };
+        height.ok_or(InvalidValue(RULE))
+    }
+}
+
+impl fmt::Display for Height {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Height::Cm(cm) => write!(f, "{}cm", cm),
+            Height::In(inches) => write!(f, "{}in", inches),
+        }
+    }
+}
+
+impl Height {
+    /// Height in centimeters
+    fn cm(self) -> f64 {
+        match self {
+            Height::Cm(cm) => f64::from(cm),
+            Height::In(inches) => f64::from(inches) * 2.54,
+        }
+    }
+
+    /// Height in inches
+    fn inches(self) -> f64 {
+        match self {
+            Height::Cm(cm) => f64::from(cm) / 2.54,
+            Height::In(inches) => f64::from(inches),
         }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct HairColor {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+impl FromStr for HairColor {
+    type Err = InvalidValue;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const RULE: &str = "# and six hex digits";
+        let hex = s
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6)
+            .filter(|hex| hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')))
+            .ok_or(InvalidValue(RULE))?;
+        let channel =
+            |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_e| InvalidValue(RULE));
         Ok(Self {
-            byr: byr.ok_or(InvalidPassport)?,
-            iyr: iyr.ok_or(InvalidPassport)?,
-            eyr: eyr.ok_or(InvalidPassport)?,
-            hgt: hgt.ok_or(InvalidPassport)?,
-            hcl: hcl.ok_or(InvalidPassport)?,
-            ecl: ecl.ok_or(InvalidPassport)?,
-            pid: pid.ok_or(InvalidPassport)?,
-            cid,
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
         })
     }
 }
 
+impl fmt::Display for HairColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EyeColor {
+    Amb,
+    Blu,
+    Brn,
+    Gry,
+    Grn,
+    Hzl,
+    Oth,
+}
+
+impl EyeColor {
+    const ALL: [EyeColor; 7] = [
+        EyeColor::Amb,
+        EyeColor::Blu,
+        EyeColor::Brn,
+        EyeColor::Gry,
+        EyeColor::Grn,
+        EyeColor::Hzl,
+        EyeColor::Oth,
+    ];
+
+    /// Abbreviation of this eye color
+    const fn abbreviation(self) -> &'static str {
+        match self {
+            EyeColor::Amb => "amb",
+            EyeColor::Blu => "blu",
+            EyeColor::Brn => "brn",
+            EyeColor::Gry => "gry",
+            EyeColor::Grn => "grn",
+            EyeColor::Hzl => "hzl",
+            EyeColor::Oth => "oth",
+        }
+    }
+}
+
+impl FromStr for EyeColor {
+    type Err = InvalidValue;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|color| color.abbreviation() == s)
+            .ok_or(InvalidValue("amb, blu, brn, gry, grn, hzl or oth"))
+    }
+}
+
+impl fmt::Display for EyeColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.abbreviation())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PassportId(u32);
+
+impl FromStr for PassportId {
+    type Err = InvalidValue;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Some(s)
+            .filter(|s| s.len() == 9 && s.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|s| s.parse().ok())
+            .map(Self)
+            .ok_or(InvalidValue("nine digits"))
+    }
+}
+
+impl fmt::Display for PassportId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:09}", self.0)
+    }
+}
+
 #[derive(Debug)]
-#[allow(dead_code)]
 struct StrictPassport {
-    byr: String,
-    iyr: String,
-    eyr: String,
-    hgt: String,
-    hcl: String,
-    ecl: String,
-    pid: String,
+    byr: u16,
+    iyr: u16,
+    eyr: u16,
+    hgt: Height,
+    hcl: HairColor,
+    ecl: EyeColor,
+    pid: PassportId,
     cid: Option<String>,
 }
 
@@ -77,42 +492,391 @@ impl FromStr for StrictPassport {
     type Err = InvalidPassport;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        lazy_static! {
-            static ref RE_BYR: Regex = Regex::new(r#"^(19[2-9]\d|200[0-2])$"#).unwrap();
-            static ref RE_IYR: Regex = Regex::new(r#"^20(1[0-9]|20)$"#).unwrap();
-            static ref RE_EYR: Regex = Regex::new(r#"^20(2[0-9]|30)$"#).unwrap();
-            static ref RE_HGT: Regex =
-                Regex::new(r#"^(1([5-8]\d|9[0-3])cm|(59|6\d|7[0-6])in)$"#).unwrap();
-            static ref RE_HCL: Regex = Regex::new(r#"^#[0-9a-f]{6}$"#).unwrap();
-            static ref RE_ECL: Regex = Regex::new(r#"^(amb|blu|brn|gry|grn|hzl|oth)$"#).unwrap();
-            static ref RE_PID: Regex = Regex::new(r#"^[0-9]{9}$"#).unwrap();
+        Self::from_fields(&PassportFields::parse(s, ParseMode::Lenient))
+    }
+}
+
+impl fmt::Display for StrictPassport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "byr:{} iyr:{} eyr:{} hgt:{} hcl:{} ecl:{} pid:{}",
+            self.byr, self.iyr, self.eyr, self.hgt, self.hcl, self.ecl, self.pid
+        )?;
+        if let Some(cid) = &self.cid {
+            write!(f, " cid:{}", cid)?;
         }
-        fn check(re: &Regex, s: String) -> Result<String, InvalidPassport> {
-            Some(s).filter(|s| re.is_match(s)).ok_or(InvalidPassport)
+        Ok(())
+    }
+}
+
+impl StrictPassport {
+    fn from_fields(fields: &PassportFields) -> Result<Self, InvalidPassport> {
+        let mut problems = fields.problems();
+        let byr = fields.check(
+            Field::Byr,
+            |s| parse_year(s, 1920, 2002, "year 1920-2002"),
+            &mut problems,
+        );
+        let iyr = fields.check(
+            Field::Iyr,
+            |s| parse_year(s, 2010, 2020, "year 2010-2020"),
+            &mut problems,
+        );
+        let eyr = fields.check(
+            Field::Eyr,
+            |s| parse_year(s, 2020, 2030, "year 2020-2030"),
+            &mut problems,
+        );
+        let hgt = fields.check(Field::Hgt, str::parse, &mut problems);
+        let hcl = fields.check(Field::Hcl, str::parse, &mut problems);
+        let ecl = fields.check(Field::Ecl, str::parse, &mut problems);
+        let pid = fields.check(Field::Pid, str::parse, &mut problems);
+        match (byr, iyr, eyr, hgt, hcl, ecl, pid) {
+            (Some(byr), Some(iyr), Some(eyr), Some(hgt), Some(hcl), Some(ecl), Some(pid))
+                if problems.is_empty() =>
+            {
+                Ok(Self {
+                    byr,
+                    iyr,
+                    eyr,
+                    hgt,
+                    hcl,
+                    ecl,
+                    pid,
+                    cid: fields.get(Field::Cid).map(String::from),
+                })
+            }
+            _ => Err(problems),
         }
+    }
+}
 
-        let passport = Passport::from_str(s)?;
-        Ok(Self {
-            byr: check(&RE_BYR, passport.byr)?,
-            iyr: check(&RE_IYR, passport.iyr)?,
-            eyr: check(&RE_EYR, passport.eyr)?,
-            hgt: check(&RE_HGT, passport.hgt)?,
-            hcl: check(&RE_HCL, passport.hcl)?,
-            ecl: check(&RE_ECL, passport.ecl)?,
-            pid: check(&RE_PID, passport.pid)?,
-            cid: passport.cid,
-        })
+/// Failure reasons aggregated across a batch of passports
+#[derive(Debug, Default)]
+struct ValidationSummary {
+    valid: usize,
+    invalid: usize,
+    missing: BTreeMap<Field, usize>,
+    invalid_fields: BTreeMap<Field, usize>,
+    unknown: BTreeMap<String, usize>,
+    duplicate: BTreeMap<Field, usize>,
+    conflicting: BTreeMap<Field, usize>,
+    malformed: usize,
+}
+
+impl ValidationSummary {
+    fn add<T>(&mut self, result: &Result<T, InvalidPassport>) {
+        let problems = match result {
+            Ok(_) => {
+                self.valid += 1;
+                return;
+            }
+            Err(problems) => problems,
+        };
+        self.invalid += 1;
+        for field in &problems.missing {
+            *self.missing.entry(*field).or_default() += 1;
+        }
+        for invalid in &problems.invalid {
+            *self.invalid_fields.entry(invalid.field).or_default() += 1;
+        }
+        for key in &problems.unknown {
+            *self.unknown.entry(key.clone()).or_default() += 1;
+        }
+        for duplicate in &problems.duplicate {
+            let counts = if duplicate.is_conflicting() {
+                &mut self.conflicting
+            } else {
+                &mut self.duplicate
+            };
+            *counts.entry(duplicate.field).or_default() += 1;
+        }
+        self.malformed += problems.malformed.len();
+    }
+}
+
+impl<'a, T: 'a> FromIterator<&'a Result<T, InvalidPassport>> for ValidationSummary {
+    fn from_iter<I: IntoIterator<Item = &'a Result<T, InvalidPassport>>>(iter: I) -> Self {
+        let mut summary = Self::default();
+        for result in iter {
+            summary.add(result);
+        }
+        summary
+    }
+}
+
+impl fmt::Display for ValidationSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  valid: {}, invalid: {}", self.valid, self.invalid)?;
+        for (field, count) in &self.missing {
+            writeln!(f, "  missing `{}`: {}", field, count)?;
+        }
+        for (field, count) in &self.invalid_fields {
+            writeln!(f, "  invalid `{}`: {}", field, count)?;
+        }
+        for (key, count) in &self.unknown {
+            writeln!(f, "  unknown key `{}`: {}", key, count)?;
+        }
+        for (field, count) in &self.duplicate {
+            writeln!(f, "  duplicate `{}`: {}", field, count)?;
+        }
+        for (field, count) in &self.conflicting {
+            writeln!(f, "  conflicting `{}`: {}", field, count)?;
+        }
+        if self.malformed > 0 {
+            writeln!(f, "  malformed pairs: {}", self.malformed)?;
+        }
+        Ok(())
+    }
+}
+
+/// Passport that can be exported field by field
+trait Record {
+    /// Value of the given field, if present
+    fn value(&self, field: Field) -> Option<String>;
+}
+
+impl Record for Passport {
+    fn value(&self, field: Field) -> Option<String> {
+        match field {
+            Field::Byr => Some(self.byr.clone()),
+            Field::Iyr => Some(self.iyr.clone()),
+            Field::Eyr => Some(self.eyr.clone()),
+            Field::Hgt => Some(self.hgt.clone()),
+            Field::Hcl => Some(self.hcl.clone()),
+            Field::Ecl => Some(self.ecl.clone()),
+            Field::Pid => Some(self.pid.clone()),
+            Field::Cid => self.cid.clone(),
+        }
+    }
+}
+
+impl Record for StrictPassport {
+    fn value(&self, field: Field) -> Option<String> {
+        match field {
+            Field::Byr => Some(self.byr.to_string()),
+            Field::Iyr => Some(self.iyr.to_string()),
+            Field::Eyr => Some(self.eyr.to_string()),
+            Field::Hgt => Some(self.hgt.to_string()),
+            Field::Hcl => Some(self.hcl.to_string()),
+            Field::Ecl => Some(self.ecl.to_string()),
+            Field::Pid => Some(self.pid.to_string()),
+            Field::Cid => self.cid.clone(),
+        }
+    }
+}
+
+impl Record for PassportFields {
+    fn value(&self, field: Field) -> Option<String> {
+        self.get(field).map(String::from)
+    }
+}
+
+/// Quote a CSV value if needed
+fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Quote a JSON string value
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            ch if ch.is_control() => json.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => json.push(ch),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Export format for passport validation results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Csv,
+    JsonLines,
+}
+
+impl Format {
+    /// Write what precedes the first record
+    fn write_header(self, w: &mut impl Write) -> io::Result<()> {
+        match self {
+            Format::Csv => {
+                let keys: Vec<_> = Field::ALL.iter().map(|field| field.key()).collect();
+                writeln!(w, "valid,{},reasons", keys.join(","))
+            }
+            Format::JsonLines => Ok(()),
+        }
+    }
+
+    /// Write a single validation result. Rejected records are written with their raw
+    /// field values and failure reasons.
+    fn write_record<T: Record>(
+        self,
+        w: &mut impl Write,
+        fields: &PassportFields,
+        result: &Result<T, InvalidPassport>,
+    ) -> io::Result<()> {
+        let record: &dyn Record = match result {
+            Ok(passport) => passport,
+            Err(_) => fields,
+        };
+        let values: Vec<_> = Field::ALL
+            .iter()
+            .map(|field| record.value(*field))
+            .collect();
+        let reasons = result
+            .as_ref()
+            .err()
+            .map(|e| e.reasons())
+            .unwrap_or_default();
+        match self {
+            Format::Csv => {
+                let values: Vec<_> = values
+                    .iter()
+                    .map(|value| csv_escape(value.as_deref().unwrap_or_default()))
+                    .collect();
+                writeln!(
+                    w,
+                    "{},{},{}",
+                    result.is_ok(),
+                    values.join(","),
+                    csv_escape(&reasons.join("; "))
+                )
+            }
+            Format::JsonLines => {
+                let values: Vec<_> = Field::ALL
+                    .iter()
+                    .zip(&values)
+                    .map(|(field, value)| {
+                        let value = value.as_deref().map(json_string);
+                        format!("\"{}\":{}", field, value.as_deref().unwrap_or("null"))
+                    })
+                    .collect();
+                let reasons: Vec<_> = reasons.iter().map(|r| json_string(r)).collect();
+                writeln!(
+                    w,
+                    "{{\"valid\":{},{},\"reasons\":[{}]}}",
+                    result.is_ok(),
+                    values.join(","),
+                    reasons.join(",")
+                )
+            }
+        }
+    }
+}
+
+/// Tallies of a single pass over a passport batch, evaluating both policies on every block
+#[derive(Debug, Default)]
+struct BatchReport {
+    presence: ValidationSummary,
+    strict: ValidationSummary,
+    heights: u32,
+    height_sum_cm: f64,
+    height_sum_in: f64,
+}
+
+impl BatchReport {
+    /// Evaluate both validation policies on the fields of a single block
+    fn add(&mut self, fields: &PassportFields) -> Result<StrictPassport, InvalidPassport> {
+        self.presence.add(&Passport::from_fields(fields));
+        let passport = StrictPassport::from_fields(fields);
+        self.strict.add(&passport);
+        if let Ok(p) = &passport {
+            self.heights += 1;
+            self.height_sum_cm += p.hgt.cm();
+            self.height_sum_in += p.hgt.inches();
+        }
+        passport
+    }
+
+    /// Average height in centimeters of passports valid under the strict policy
+    fn average_height_cm(&self) -> Option<f64> {
+        Some(self.height_sum_cm / f64::from(self.heights)).filter(|_| self.heights > 0)
+    }
+
+    /// Average height in inches of passports valid under the strict policy
+    fn average_height_in(&self) -> Option<f64> {
+        Some(self.height_sum_in / f64::from(self.heights)).filter(|_| self.heights > 0)
+    }
+}
+
+/// Counts of valid passports, followed by failure summaries in alternate form (`{:#}`)
+impl fmt::Display for BatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Valid passports: {}", self.presence.valid)?;
+        if f.alternate() {
+            write!(f, "{}", self.presence)?;
+        }
+        writeln!(f, "Valid strict passports: {}", self.strict.valid)?;
+        if let (Some(cm), Some(inches)) = (self.average_height_cm(), self.average_height_in()) {
+            writeln!(
+                f,
+                "Average height of valid strict passports: {:.1}cm ({:.1}in)",
+                cm, inches
+            )?;
+        }
+        if f.alternate() {
+            write!(f, "{}", self.strict)?;
+        }
+        Ok(())
     }
 }
 
 fn main() -> Result<(), Box<dyn error::Error>> {
-    let passports: Vec<Result<Passport, _>> = Input::day(4)?.iter_parsed_blocks().collect();
-    let count = passports.iter().filter(|p| p.is_ok()).count();
-    println!("Valid passports: {}", count);
+    let args: Vec<String> = env::args().skip(1).collect();
+    let flag = |name: &str| args.iter().any(|arg| arg == name);
+    let mode = if flag("--strict") {
+        ParseMode::Strict
+    } else {
+        ParseMode::Lenient
+    };
+    let export = if flag("--csv") {
+        Some(Format::Csv)
+    } else if flag("--json") {
+        Some(Format::JsonLines)
+    } else {
+        None
+    };
+    let valid_only = flag("--valid");
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if let Some(format) = export {
+        format.write_header(&mut out)?;
+    }
 
-    let passports: Vec<Result<StrictPassport, _>> = Input::day(4)?.iter_parsed_blocks().collect();
-    let count = passports.iter().filter(|p| p.is_ok()).count();
-    println!("Valid strict passports: {}", count);
+    let mut report = BatchReport::default();
+    for block in Input::day(4)?.iter_blocks() {
+        let fields = PassportFields::parse(&block?, mode);
+        let passport = report.add(&fields);
+        if let Some(format) = export.filter(|_| passport.is_ok() || !valid_only) {
+            format.write_record(&mut out, &fields, &passport)?;
+        }
+    }
+
+    let report = if flag("--summary") {
+        format!("{:#}", report)
+    } else {
+        report.to_string()
+    };
+    if export.is_some() {
+        // Exported records go to stdout, so keep the report out of their way
+        eprint!("{}", report);
+    } else {
+        print!("{}", report);
+    }
 
     Ok(())
 }
@@ -161,4 +925,170 @@ mod tests {
         assert!(passports[6].is_ok());
         assert!(passports[7].is_ok());
     }
+
+    #[test]
+    fn diagnostics() {
+        let err = INPUT_1[3].parse::<Passport>().unwrap_err();
+        assert_eq!(err.missing, [Field::Byr]);
+        assert!(err.invalid.is_empty());
+
+        let err = INPUT_2[3].parse::<StrictPassport>().unwrap_err();
+        assert!(err.missing.is_empty());
+        let invalid: Vec<_> = err.invalid.iter().map(|i| i.field).collect();
+        assert_eq!(
+            invalid,
+            [
+                Field::Byr,
+                Field::Iyr,
+                Field::Eyr,
+                Field::Hgt,
+                Field::Hcl,
+                Field::Ecl,
+                Field::Pid
+            ]
+        );
+        assert_eq!(err.invalid[3].value, "59cm");
+
+        let fields = PassportFields::parse("byr:1937 byr:1937 foo:bar iyr", ParseMode::Strict);
+        let err = StrictPassport::from_fields(&fields).unwrap_err();
+        assert_eq!(err.duplicate[0].field, Field::Byr);
+        assert!(!err.duplicate[0].is_conflicting());
+        assert_eq!(err.unknown, ["foo"]);
+        assert_eq!(err.malformed, ["iyr"]);
+        assert_eq!(err.missing.len(), 6);
+    }
+
+    #[test]
+    fn typed_fields() {
+        assert_eq!("183cm".parse::<Height>().unwrap(), Height::Cm(183));
+        assert_eq!("60in".parse::<Height>().unwrap().cm(), 152.4);
+        assert!((Height::Cm(127).inches() - 50.0).abs() < 1e-9);
+        assert!("190in".parse::<Height>().is_err());
+        assert!("190".parse::<Height>().is_err());
+        let color: HairColor = "#123abc".parse().unwrap();
+        assert_eq!((color.r, color.g, color.b), (0x12, 0x3a, 0xbc));
+        assert!("#123abz".parse::<HairColor>().is_err());
+        assert_eq!("brn".parse::<EyeColor>().unwrap(), EyeColor::Brn);
+        assert!("wat".parse::<EyeColor>().is_err());
+        assert_eq!("000000001".parse::<PassportId>().unwrap(), PassportId(1));
+        assert!("0123456789".parse::<PassportId>().is_err());
+
+        let passports: Vec<StrictPassport> =
+            INPUT_2[4..].iter().map(|s| s.parse().unwrap()).collect();
+        assert_eq!(passports[0].byr, 1980);
+        assert_eq!(passports[0].hcl.to_string(), "#623a2f");
+        assert_eq!(passports[3].pid.to_string(), "093154719");
+        assert_eq!(
+            passports[0].to_string(),
+            "byr:1980 iyr:2012 eyr:2030 hgt:74in hcl:#623a2f ecl:grn pid:087499704"
+        );
+    }
+
+    #[test]
+    fn batch_report() {
+        let mut report = BatchReport::default();
+        for s in INPUT_1.iter().chain(&INPUT_2) {
+            let _passport = report.add(&PassportFields::parse(s, ParseMode::Strict));
+        }
+        assert_eq!(report.presence.valid, 2 + 8);
+        assert_eq!(report.strict.valid, 2 + 4);
+        assert_eq!(report.strict.invalid, 2 + 4);
+        let height = report.average_height_cm().unwrap();
+        assert!((height - 172.8).abs() < 0.1);
+        let height = report.average_height_in().unwrap();
+        assert!((height - 68.0).abs() < 0.1);
+        assert!(report.to_string().starts_with("Valid passports: 10\n"));
+    }
+
+    #[test]
+    fn export() {
+        let mut csv = Vec::new();
+        Format::Csv.write_header(&mut csv).unwrap();
+        for s in &INPUT_2[2..5] {
+            let fields = PassportFields::parse(s, ParseMode::Lenient);
+            let passport = StrictPassport::from_fields(&fields);
+            Format::Csv
+                .write_record(&mut csv, &fields, &passport)
+                .unwrap();
+        }
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines[0], "valid,byr,iyr,eyr,hgt,hcl,ecl,pid,cid,reasons");
+        assert_eq!(
+            lines[1],
+            "false,1992,2012,2020,182cm,dab227,brn,021572410,277,\
+             invalid `hcl` value `dab227` (# and six hex digits)"
+        );
+        assert!(lines[2].starts_with(
+            "false,2007,2023,2038,59cm,74454a,zzz,3556412378,,\"invalid `byr` value `2007`"
+        ));
+        assert!(lines[2].contains("(amb, blu, brn, gry, grn, hzl or oth)"));
+        assert_eq!(lines[3], "true,1980,2012,2030,74in,#623a2f,grn,087499704,,");
+
+        let mut json = Vec::new();
+        for s in &INPUT_1[..2] {
+            let fields = PassportFields::parse(s, ParseMode::Lenient);
+            let passport = Passport::from_fields(&fields);
+            Format::JsonLines
+                .write_record(&mut json, &fields, &passport)
+                .unwrap();
+        }
+        let json = String::from_utf8(json).unwrap();
+        let lines: Vec<_> = json.lines().collect();
+        assert_eq!(
+            lines[0],
+            "{\"valid\":true,\"byr\":\"1937\",\"iyr\":\"2017\",\"eyr\":\"2020\",\"hgt\":\"183cm\",\"hcl\":\"#fffffd\",\"ecl\":\"gry\",\"pid\":\"860033327\",\"cid\":\"147\",\"reasons\":[]}"
+        );
+        assert_eq!(
+            lines[1],
+            "{\"valid\":false,\"byr\":\"1929\",\"iyr\":\"2013\",\"eyr\":\"2023\",\"hgt\":null,\"hcl\":\"#cfa07d\",\"ecl\":\"amb\",\"pid\":\"028048884\",\"cid\":\"350\",\"reasons\":[\"missing `hgt`\"]}"
+        );
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\n\"");
+    }
+
+    #[test]
+    fn parse_modes() {
+        let s = "byr:1937 byr:1940 iyr:2017 iyr:2017 eyr:2020:2021 hgt: hcl:#fffffd ecl:gry pid:860033327";
+
+        let fields = PassportFields::parse(s, ParseMode::Strict);
+        let err = Passport::from_fields(&fields).unwrap_err();
+        assert_eq!(err.duplicate[0].field, Field::Byr);
+        assert_eq!(err.duplicate[0].values, ["1937", "1940"]);
+        assert!(err.duplicate[0].is_conflicting());
+        assert_eq!(err.duplicate[1].field, Field::Iyr);
+        assert!(!err.duplicate[1].is_conflicting());
+        assert_eq!(err.malformed, ["eyr:2020:2021"]);
+        assert_eq!(err.invalid[0].field, Field::Hgt);
+        assert_eq!(err.missing, [Field::Eyr]);
+        assert_eq!(err.reasons()[1], "invalid `hgt` value `` (non-empty value)");
+        assert_eq!(err.reasons()[2], "conflicting `byr` values `1937`, `1940`");
+        assert_eq!(fields.get(Field::Byr), Some("1937"));
+
+        let fields = PassportFields::parse(s, ParseMode::Lenient);
+        let passport = Passport::from_fields(&fields).unwrap();
+        assert_eq!(passport.byr, "1940");
+        assert_eq!(s.parse::<Passport>().unwrap().byr, "1940");
+        assert_eq!(passport.eyr, "2020:2021");
+        assert_eq!(passport.hgt, "");
+        assert!(StrictPassport::from_fields(&fields).is_err());
+
+        // Keys repeated after an empty value are duplicates, not a first value
+        let s = "byr: byr:1937 iyr:2017 eyr:2020 hgt:183cm hcl:#fffffd ecl:gry pid:860033327";
+        let fields = PassportFields::parse(s, ParseMode::Strict);
+        let err = Passport::from_fields(&fields).unwrap_err();
+        assert_eq!(err.duplicate[0].field, Field::Byr);
+        assert_eq!(err.duplicate[0].values, ["", "1937"]);
+        assert_eq!(err.invalid[0].field, Field::Byr);
+        assert_eq!(fields.get(Field::Byr), None);
+    }
+
+    #[test]
+    fn summary() {
+        let passports: Vec<Result<StrictPassport, _>> = INPUT_2.iter().map(|s| s.parse()).collect();
+        let summary: ValidationSummary = passports.iter().collect();
+        assert_eq!(summary.valid, 4);
+        assert_eq!(summary.invalid, 4);
+        assert_eq!(summary.invalid_fields[&Field::Eyr], 3);
+        assert_eq!(summary.missing.get(&Field::Byr), None);
+    }
 }
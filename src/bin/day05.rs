@@ -1,72 +1,563 @@
 use advent_of_code_2020::Input;
-use std::error;
-use std::str::FromStr;
+use std::cmp::Reverse;
+use std::fs::File;
+use std::hint::black_box;
+use std::io::{self, BufRead, BufReader};
+use std::ops::Range;
+use std::str::{Chars, FromStr};
+use std::time::Instant;
+use std::{env, error, fmt};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 #[error("Invalid seat")]
 struct InvalidSeat;
 
-#[derive(Debug, PartialOrd, Ord, PartialEq, Eq)]
-struct SeatNumber(usize);
+#[derive(Debug, Error)]
+#[error("Invalid boarding policy")]
+struct InvalidPolicy;
 
-impl FromStr for SeatNumber {
-    type Err = InvalidSeat;
+#[derive(Debug, Error)]
+#[error("Aircraft with {seats} seats is too small for {passengers} passengers")]
+struct AircraftFull {
+    seats: usize,
+    passengers: usize,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut chars = s.chars();
-        let mut row = 0..128;
-        for _ in 0..7 {
-            match chars.next() {
-                Some('F') => row.end -= row.len() / 2,
-                Some('B') => row.start += row.len() / 2,
-                _ => return Err(InvalidSeat),
-            }
+/// Number of binary space partitioning steps needed to single out one of `n` positions
+const fn partition_steps(n: usize) -> usize {
+    if n <= 1 {
+        0
+    } else {
+        (usize::BITS - (n - 1).leading_zeros()) as usize
+    }
+}
+
+/// Seat dimensions and boarding pass encoding of an aircraft
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AircraftLayout {
+    /// Number of rows
+    rows: usize,
+    /// Number of seats in each row
+    columns: usize,
+    /// Letters selecting the front and back half of the remaining rows
+    row_letters: (char, char),
+    /// Letters selecting the left and right half of the remaining columns
+    column_letters: (char, char),
+    /// Seat id factor of the row (`row * row_multiplier + column`). Seat ids are only
+    /// unique if this is at least `columns`.
+    row_multiplier: usize,
+}
+
+impl Default for AircraftLayout {
+    fn default() -> Self {
+        Self {
+            rows: 128,
+            columns: 8,
+            row_letters: ('F', 'B'),
+            column_letters: ('L', 'R'),
+            row_multiplier: 8,
         }
-        let mut column = 0..8;
-        for _ in 0..3 {
+    }
+}
+
+impl AircraftLayout {
+    /// Narrow down `0..n` by binary space partitioning using the given letters
+    fn partition(chars: &mut Chars<'_>, n: usize, (lower, upper): (char, char)) -> Option<usize> {
+        let mut range: Range<usize> = 0..1 << partition_steps(n);
+        for _ in 0..partition_steps(n) {
             match chars.next() {
-                Some('L') => column.end -= column.len() / 2,
-                Some('R') => column.start += column.len() / 2,
-                _ => return Err(InvalidSeat),
+                Some(ch) if ch == lower => range.end -= range.len() / 2,
+                Some(ch) if ch == upper => range.start += range.len() / 2,
+                _ => return None,
             }
         }
-        if row.len() != 1 || column.len() != 1 {
-            Err(InvalidSeat)
+        Some(range.start).filter(|pos| *pos < n)
+    }
+
+    /// Decode a boarding pass by narrowing down row and column ranges
+    fn decode(&self, s: &str) -> Result<SeatNumber, InvalidSeat> {
+        let mut chars = s.chars();
+        let row = Self::partition(&mut chars, self.rows, self.row_letters).ok_or(InvalidSeat)?;
+        let column =
+            Self::partition(&mut chars, self.columns, self.column_letters).ok_or(InvalidSeat)?;
+        if chars.next().is_some() {
+            return Err(InvalidSeat);
+        }
+        self.seat(row, column).ok_or(InvalidSeat)
+    }
+
+    /// Read letters as binary number with the upper letter as one, and whether all letters were valid
+    fn bits(chars: impl Iterator<Item = char>, (lower, upper): (char, char)) -> (usize, bool) {
+        chars.fold((0, true), |(n, valid), ch| {
+            (
+                n << 1 | usize::from(ch == upper),
+                valid & ((ch == lower) | (ch == upper)),
+            )
+        })
+    }
+
+    /// Decode a boarding pass by reading row and column letters as binary numbers
+    fn decode_bits(&self, s: &str) -> Result<SeatNumber, InvalidSeat> {
+        let row_steps = partition_steps(self.rows);
+        let column_steps = partition_steps(self.columns);
+        if s.chars().count() != row_steps + column_steps {
+            return Err(InvalidSeat);
+        }
+        let (row, row_valid) = Self::bits(s.chars().take(row_steps), self.row_letters);
+        let (column, column_valid) = Self::bits(s.chars().skip(row_steps), self.column_letters);
+        if !(row_valid & column_valid) {
+            return Err(InvalidSeat);
+        }
+        self.seat(row, column).ok_or(InvalidSeat)
+    }
+
+    /// Encode a seat as boarding pass
+    fn encode(&self, seat: &SeatNumber) -> String {
+        let letters = |pos: usize, n: usize, (lower, upper): (char, char)| {
+            (0..partition_steps(n))
+                .rev()
+                .map(move |bit| if pos >> bit & 1 == 0 { lower } else { upper })
+        };
+        letters(seat.row(), self.rows, self.row_letters)
+            .chain(letters(seat.column(), self.columns, self.column_letters))
+            .collect()
+    }
+
+    /// Seat at the given row and column, if it exists on the aircraft
+    fn seat(&self, row: usize, column: usize) -> Option<SeatNumber> {
+        if row < self.rows && column < self.columns {
+            Some(SeatNumber {
+                id: row * self.row_multiplier + column,
+                row,
+                column,
+            })
         } else {
-            Ok(Self(row.start * 8 + column.start))
+            None
+        }
+    }
+
+    /// Seat with the given id, if it exists on the aircraft. None if seat ids aren't
+    /// unique because the row multiplier is zero or less than the number of columns.
+    fn seat_with_id(&self, id: usize) -> Option<SeatNumber> {
+        if self.row_multiplier == 0 || self.row_multiplier < self.columns {
+            return None;
         }
+        self.seat(id / self.row_multiplier, id % self.row_multiplier)
+    }
+
+    /// All seats of the aircraft in row and column order
+    fn seats(&self) -> impl Iterator<Item = SeatNumber> + '_ {
+        (0..self.rows)
+            .flat_map(move |row| (0..self.columns).filter_map(move |column| self.seat(row, column)))
+    }
+}
+
+#[derive(Debug, PartialOrd, Ord, PartialEq, Eq)]
+struct SeatNumber {
+    id: usize,
+    row: usize,
+    column: usize,
+}
+
+/// Parse a boarding pass of the puzzle aircraft
+impl FromStr for SeatNumber {
+    type Err = InvalidSeat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AircraftLayout::default().decode_bits(s)
+    }
+}
+
+/// Boarding pass of a seat of the puzzle aircraft
+impl fmt::Display for SeatNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&AircraftLayout::default().encode(self))
     }
 }
 
 impl SeatNumber {
+    /// Seat at the given row and column, if it exists on the puzzle aircraft
+    fn from_row_column(row: usize, column: usize) -> Option<Self> {
+        AircraftLayout::default().seat(row, column)
+    }
+
     const fn id(&self) -> usize {
-        self.0
+        self.id
     }
 
-    #[allow(dead_code)]
     const fn row(&self) -> usize {
-        self.id() / 8
+        self.row
     }
 
-    #[allow(dead_code)]
     const fn column(&self) -> usize {
-        self.id() % 8
+        self.column
+    }
+}
+
+/// Occupancy of every seat of an aircraft, according to a list of boarding passes
+#[derive(Debug)]
+struct SeatMap {
+    layout: AircraftLayout,
+    /// Number of boarding passes for each seat, in row and column order
+    passes: Vec<usize>,
+}
+
+impl SeatMap {
+    fn new(layout: AircraftLayout, seats: &[SeatNumber]) -> Self {
+        let mut passes = vec![0; layout.rows * layout.columns];
+        for seat in seats {
+            passes[seat.row() * layout.columns + seat.column()] += 1;
+        }
+        Self { layout, passes }
+    }
+
+    /// All seats in row and column order, with their number of boarding passes
+    fn seats(&self) -> impl Iterator<Item = (SeatNumber, usize)> + '_ {
+        self.layout.seats().zip(self.passes.iter().copied())
+    }
+
+    /// Positions of the first and last occupied seat
+    fn occupied_range(&self) -> Option<(usize, usize)> {
+        let first = self.passes.iter().position(|n| *n > 0)?;
+        let last = self.passes.iter().rposition(|n| *n > 0)?;
+        Some((first, last))
+    }
+
+    /// Free seats between the first and last occupied seat
+    fn gaps(&self) -> Vec<SeatNumber> {
+        let (first, last) = match self.occupied_range() {
+            Some(range) => range,
+            None => return Vec::new(),
+        };
+        self.seats()
+            .skip(first)
+            .take(last - first)
+            .filter(|(_seat, passes)| *passes == 0)
+            .map(|(seat, _passes)| seat)
+            .collect()
+    }
+
+    /// Seats with more than one boarding pass, with their number of boarding passes
+    fn duplicates(&self) -> Vec<(SeatNumber, usize)> {
+        self.seats().filter(|(_seat, passes)| *passes > 1).collect()
+    }
+}
+
+/// Grid of rows and columns, with occupied seats as `#`, free seats as `.` and seats
+/// in front of the first or behind the last occupied seat (which are missing) as ` `
+impl fmt::Display for SeatMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (first, last) = self.occupied_range().unwrap_or((usize::MAX, 0));
+        for (pos, (seat, passes)) in self.seats().enumerate() {
+            if seat.column() == 0 {
+                write!(f, "{:>4} ", seat.row())?;
+            }
+            let marker = match passes {
+                0 if pos < first || pos > last => ' ',
+                0 => '.',
+                _ => '#',
+            };
+            write!(f, "{}", marker)?;
+            if seat.column() + 1 == self.layout.columns {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of looking for my seat among the boarding passes
+#[derive(Debug, PartialEq, Eq)]
+enum MySeat {
+    /// The only free seat id between the lowest and highest id on the boarding passes
+    Found(usize),
+    /// No free seat id between the lowest and highest id
+    NoGap,
+    /// Several free seat ids between the lowest and highest id
+    Ambiguous(Vec<usize>),
+    /// A seat id that is on more than one boarding pass
+    Duplicate(usize),
+}
+
+/// Find my seat, which is the only missing id between the lowest and highest id on the
+/// boarding passes. Marks every id of that range in a table, so it runs in O(n) without
+/// sorting and detects duplicate ids along the way.
+fn find_my_seat(seats: &[SeatNumber]) -> MySeat {
+    let ids = seats.iter().map(SeatNumber::id);
+    let (min, max) = match (ids.clone().min(), ids.clone().max()) {
+        (Some(min), Some(max)) => (min, max),
+        _ => return MySeat::NoGap,
+    };
+    let mut seen = vec![false; max - min + 1];
+    for id in ids {
+        if seen[id - min] {
+            return MySeat::Duplicate(id);
+        }
+        seen[id - min] = true;
     }
+    let mut free: Vec<usize> = (min..=max)
+        .zip(&seen)
+        .filter(|(_id, seen)| !**seen)
+        .map(|(id, _seen)| id)
+        .collect();
+    match free.len() {
+        0 => MySeat::NoGap,
+        1 => MySeat::Found(free.remove(0)),
+        _ => MySeat::Ambiguous(free),
+    }
+}
+
+/// Small xorshift pseudo random number generator, for reproducible simulations
+#[derive(Debug)]
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // Scramble the seed so that similar seeds don't give similar sequences (and
+        // zero, which xorshift would never leave, can't occur)
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Self((z ^ (z >> 31)).max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Random number below n
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Order in which seats are handed out to boarding passengers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BoardingPolicy {
+    /// Window seats first, then seats further towards the aisle, each from back to front
+    WindowFirst,
+    /// Rows from back to front
+    BackToFront,
+    /// Random order, reproducible with the given seed
+    Random(u64),
+}
+
+/// Parse `window-first`, `back-to-front`, `random` or `random:SEED`
+impl FromStr for BoardingPolicy {
+    type Err = InvalidPolicy;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "window-first" => Ok(BoardingPolicy::WindowFirst),
+            "back-to-front" => Ok(BoardingPolicy::BackToFront),
+            "random" => Ok(BoardingPolicy::Random(0)),
+            _ => s
+                .strip_prefix("random:")
+                .and_then(|seed| seed.parse().ok())
+                .map(BoardingPolicy::Random)
+                .ok_or(InvalidPolicy),
+        }
+    }
+}
+
+impl BoardingPolicy {
+    /// Seats of the given aircraft in the order they are handed out
+    fn seat_order(self, layout: &AircraftLayout) -> Vec<SeatNumber> {
+        let mut seats: Vec<_> = layout.seats().collect();
+        let window_distance =
+            |seat: &SeatNumber| seat.column().min(layout.columns - 1 - seat.column());
+        match self {
+            BoardingPolicy::WindowFirst => seats
+                .sort_by_key(|seat| (window_distance(seat), Reverse(seat.row()), seat.column())),
+            BoardingPolicy::BackToFront => {
+                seats.sort_by_key(|seat| (Reverse(seat.row()), seat.column()))
+            }
+            BoardingPolicy::Random(seed) => {
+                let mut rng = XorShift::new(seed);
+                for i in (1..seats.len()).rev() {
+                    seats.swap(i, rng.below(i + 1));
+                }
+            }
+        }
+        seats
+    }
+}
+
+/// Passenger taking a seat during a boarding simulation
+#[derive(Debug)]
+struct Boarding<'a> {
+    /// Position in the boarding timeline
+    time: usize,
+    passenger: &'a str,
+    seat: SeatNumber,
+    pass: String,
+}
+
+impl fmt::Display for Boarding<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>5} {} {} (row {}, column {}, seat id {})",
+            self.time,
+            self.passenger,
+            self.pass,
+            self.seat.row(),
+            self.seat.column(),
+            self.seat.id()
+        )
+    }
+}
+
+/// Board the given passengers in order, assigning seats according to the given policy
+/// Passenger names, one per line, ignoring blank lines
+fn read_passengers<R: BufRead>(reader: R) -> io::Result<Vec<String>> {
+    reader
+        .lines()
+        .filter(|line| !line.as_ref().is_ok_and(|name| name.trim().is_empty()))
+        .map(|line| line.map(|name| name.trim().to_string()))
+        .collect()
+}
+
+fn simulate<'a>(
+    layout: &AircraftLayout,
+    passengers: &'a [String],
+    policy: BoardingPolicy,
+) -> Result<Vec<Boarding<'a>>, AircraftFull> {
+    let seats = policy.seat_order(layout);
+    if passengers.len() > seats.len() {
+        return Err(AircraftFull {
+            seats: seats.len(),
+            passengers: passengers.len(),
+        });
+    }
+    Ok(passengers
+        .iter()
+        .zip(seats)
+        .enumerate()
+        .map(|(time, (passenger, seat))| Boarding {
+            time,
+            passenger,
+            pass: layout.encode(&seat),
+            seat,
+        })
+        .collect())
+}
+
+/// Boarding pass decoding function
+type Decoder = fn(&AircraftLayout, &str) -> Result<SeatNumber, InvalidSeat>;
+
+/// Compare decoder speeds on a manifest of a million boarding passes
+fn bench(layout: &AircraftLayout) -> Result<(), InvalidSeat> {
+    let seats = layout.rows * layout.columns;
+    let manifest: Vec<String> = (0..1_000_000)
+        .filter_map(|i| layout.seat(i * 7919 % seats / layout.columns, i % layout.columns))
+        .map(|seat| layout.encode(&seat))
+        .collect();
+
+    let decoders: [(&str, Decoder); 2] = [
+        ("range halving", AircraftLayout::decode),
+        ("bits", AircraftLayout::decode_bits),
+    ];
+    for (name, decode) in &decoders {
+        let start = Instant::now();
+        let sum = manifest.iter().try_fold(0, |sum, pass| {
+            decode(layout, black_box(pass)).map(|seat| sum + seat.id())
+        })?;
+        println!(
+            "Decoded {} boarding passes using {} in {:?} (id sum {})",
+            manifest.len(),
+            name,
+            start.elapsed(),
+            sum
+        );
+    }
+    Ok(())
 }
 
 fn main() -> Result<(), Box<dyn error::Error>> {
-    let mut seats: Vec<SeatNumber> = Input::day(5)?.parsed_lines()?;
+    let layout = AircraftLayout::default();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--bench") {
+        bench(&layout)?;
+        return Ok(());
+    }
+    if let Some(pos) = args.iter().position(|arg| arg == "--pass") {
+        // Boarding pass of the seat at the given row and column, e.g. `--pass 44 5`
+        let row = args.get(pos + 1).ok_or(InvalidSeat)?.parse()?;
+        let column = args.get(pos + 2).ok_or(InvalidSeat)?.parse()?;
+        let seat = SeatNumber::from_row_column(row, column).ok_or(InvalidSeat)?;
+        println!("{}", seat);
+        return Ok(());
+    }
+    if let Some(pos) = args.iter().position(|arg| arg == "--simulate") {
+        // Boarding passes go to stdout in puzzle input format, the timeline to stderr.
+        // Passengers are read from `--passengers FILE` (`-` for stdin), otherwise the
+        // given number of passengers is made up.
+        let policy: BoardingPolicy = args.get(pos + 1).ok_or(InvalidPolicy)?.parse()?;
+        let file = args
+            .iter()
+            .position(|arg| arg == "--passengers")
+            .map(|pos| args.get(pos + 1).ok_or("Missing passenger list"))
+            .transpose()?;
+        let passengers = match file.map(String::as_str) {
+            Some("-") => read_passengers(io::stdin().lock())?,
+            Some(path) => read_passengers(BufReader::new(File::open(path)?))?,
+            None => {
+                let count = match args.get(pos + 2).filter(|arg| !arg.starts_with("--")) {
+                    Some(count) => count.parse()?,
+                    None => layout.rows * layout.columns,
+                };
+                (1..=count).map(|i| format!("P{:04}", i)).collect()
+            }
+        };
+        for boarding in simulate(&layout, &passengers, policy)? {
+            println!("{}", boarding.pass);
+            eprintln!("{}", boarding);
+        }
+        return Ok(());
+    }
 
-    let max = seats.iter().max_by_key(|s| s.id()).unwrap().id();
+    let seats: Vec<SeatNumber> = Input::day(5)?.parsed_lines()?;
+
+    let max = seats
+        .iter()
+        .map(SeatNumber::id)
+        .max()
+        .ok_or("No boarding passes")?;
     println!("Highest seat id: {}", max);
 
-    seats.sort();
-    let my = (1..seats.len())
-        .find(|i| seats[*i - 1].id() + 2 == seats[*i].id())
-        .map(|i| seats[i - 1].id() + 1)
-        .unwrap();
-    println!("My seat id: {}", my);
+    // Report occupancy first, so gaps and duplicates are shown even if my seat is unclear
+    let map = SeatMap::new(layout, &seats);
+    if args.iter().any(|arg| arg == "--map") {
+        print!("{}", map);
+    }
+    let gaps: Vec<_> = map.gaps().iter().map(SeatNumber::id).collect();
+    println!("Free seat ids between occupied seats: {:?}", gaps);
+    for (seat, passes) in map.duplicates() {
+        println!("Duplicate boarding pass: {} ({} times)", seat, passes);
+    }
+
+    match find_my_seat(&seats) {
+        MySeat::Found(id) => {
+            let my = map.layout.seat_with_id(id).ok_or(InvalidSeat)?;
+            println!("My seat id: {}", my.id());
+            println!("My boarding pass: {}", my);
+        }
+        MySeat::NoGap => println!("Unable to find my seat: no free seat between passes"),
+        MySeat::Ambiguous(ids) => {
+            println!("Unable to find my seat: several free seats {:?}", ids)
+        }
+        MySeat::Duplicate(id) => {
+            println!(
+                "Unable to find my seat: seat id {} is on several passes",
+                id
+            )
+        }
+    }
 
     Ok(())
 }
@@ -97,4 +588,162 @@ mod tests {
         assert_eq!(seat.column(), 4);
         assert_eq!(seat.id(), 820);
     }
+
+    #[test]
+    fn encoding() {
+        let seat = SeatNumber::from_row_column(44, 5).unwrap();
+        assert_eq!(seat.id(), 357);
+        assert_eq!(seat.to_string(), "FBFBBFFRLR");
+        assert!(SeatNumber::from_row_column(128, 0).is_none());
+        assert!(SeatNumber::from_row_column(0, 8).is_none());
+
+        let layout = AircraftLayout::default();
+        for id in 0..1024 {
+            let pass = layout.seat_with_id(id).unwrap().to_string();
+            assert_eq!(pass.parse::<SeatNumber>().unwrap().id(), id);
+        }
+    }
+
+    #[test]
+    fn decoders() {
+        let layout = AircraftLayout::default();
+        for id in 0..1024 {
+            let pass = layout.seat_with_id(id).unwrap().to_string();
+            assert_eq!(layout.decode(&pass).ok(), layout.decode_bits(&pass).ok());
+        }
+        for pass in &["FBFBBFFRLRL", "FBFBBFFRL", "FBFBBFFRLF", "FBFBBFFRL\n", ""] {
+            assert!(layout.decode(pass).is_err());
+            assert!(layout.decode_bits(pass).is_err());
+        }
+    }
+
+    #[test]
+    fn part_2() {
+        let layout = AircraftLayout::default();
+        let seats = |ids: &[usize]| -> Vec<SeatNumber> {
+            ids.iter()
+                .map(|id| layout.seat_with_id(*id).unwrap())
+                .collect()
+        };
+        assert_eq!(find_my_seat(&seats(&[8, 5, 4, 7])), MySeat::Found(6));
+        assert_eq!(find_my_seat(&seats(&[2, 0])), MySeat::Found(1));
+        assert_eq!(find_my_seat(&seats(&[5, 4, 6])), MySeat::NoGap);
+        assert_eq!(find_my_seat(&[]), MySeat::NoGap);
+        assert_eq!(
+            find_my_seat(&seats(&[10, 4, 7])),
+            MySeat::Ambiguous(vec![5, 6, 8, 9])
+        );
+        assert_eq!(find_my_seat(&seats(&[4, 6, 4])), MySeat::Duplicate(4));
+    }
+
+    #[test]
+    fn simulation() {
+        let layout = AircraftLayout {
+            rows: 3,
+            columns: 4,
+            ..AircraftLayout::default()
+        };
+        let order = |policy: BoardingPolicy| -> Vec<_> {
+            policy
+                .seat_order(&layout)
+                .iter()
+                .map(|seat| (seat.row(), seat.column()))
+                .collect()
+        };
+        assert_eq!(
+            order(BoardingPolicy::WindowFirst)[..8],
+            [
+                (2, 0),
+                (2, 3),
+                (1, 0),
+                (1, 3),
+                (0, 0),
+                (0, 3),
+                (2, 1),
+                (2, 2)
+            ]
+        );
+        assert_eq!(
+            order(BoardingPolicy::BackToFront)[..5],
+            [(2, 0), (2, 1), (2, 2), (2, 3), (1, 0)]
+        );
+        let random = order(BoardingPolicy::Random(42));
+        assert_eq!(random, order("random:42".parse().unwrap()));
+        assert_ne!(random, order(BoardingPolicy::Random(43)));
+        let mut sorted = random.clone();
+        sorted.sort_unstable();
+        let all: Vec<_> = layout
+            .seats()
+            .map(|seat| (seat.row(), seat.column()))
+            .collect();
+        assert_eq!(sorted, all);
+
+        let passengers = read_passengers(&b"Ann\n\n Bob \nCy\n"[..]).unwrap();
+        assert_eq!(passengers, ["Ann", "Bob", "Cy"]);
+        let boardings = simulate(&layout, &passengers, BoardingPolicy::BackToFront).unwrap();
+        assert_eq!(boardings[1].passenger, "Bob");
+        assert_eq!(boardings[1].pass, "BFLR");
+        assert_eq!(
+            layout.decode(&boardings[1].pass).unwrap(),
+            boardings[1].seat
+        );
+        assert_eq!(
+            boardings[1].to_string(),
+            "    1 Bob BFLR (row 2, column 1, seat id 17)"
+        );
+        let passengers = vec![String::new(); 13];
+        assert!(simulate(&layout, &passengers, BoardingPolicy::WindowFirst).is_err());
+    }
+
+    #[test]
+    fn seat_map() {
+        let layout = AircraftLayout {
+            rows: 4,
+            columns: 3,
+            ..AircraftLayout::default()
+        };
+        let seats: Vec<SeatNumber> = ["FBLR", "FBRL", "BFLL", "BFRL", "BFRL", "BBLL"]
+            .iter()
+            .map(|pass| layout.decode(pass).unwrap())
+            .collect();
+        let map = SeatMap::new(layout, &seats);
+        assert_eq!(map.to_string(), "   0    \n   1  ##\n   2 #.#\n   3 #  \n");
+        let gaps: Vec<_> = map.gaps().iter().map(SeatNumber::id).collect();
+        assert_eq!(gaps, [17]);
+        let duplicates: Vec<_> = map
+            .duplicates()
+            .iter()
+            .map(|(seat, passes)| (seat.id(), *passes))
+            .collect();
+        assert_eq!(duplicates, [(18, 2)]);
+    }
+
+    #[test]
+    fn layouts() {
+        let layout = AircraftLayout {
+            rows: 40,
+            columns: 6,
+            row_letters: ('V', 'H'),
+            column_letters: ('A', 'Z'),
+            row_multiplier: 10,
+        };
+        let seat = layout.decode("HVVVHHAZA").unwrap();
+        assert_eq!((seat.row(), seat.column(), seat.id()), (35, 2, 352));
+        assert_eq!(layout.encode(&seat), "HVVVHHAZA");
+        assert_eq!(layout.decode_bits("HVVVHHAZA").unwrap(), seat);
+        assert!(layout.decode("HVHVVVAAA").is_err());
+        assert!(layout.decode_bits("HVHVVVAAA").is_err());
+        assert!(layout.decode("HVVVHHZZA").is_err());
+        assert!(layout.decode("FBFBBFFRLR").is_err());
+        assert!(layout.seat_with_id(17).is_none());
+        assert_eq!(layout.seat_with_id(15).unwrap().column(), 5);
+
+        for row_multiplier in &[0, 5] {
+            let layout = AircraftLayout {
+                row_multiplier: *row_multiplier,
+                ..layout
+            };
+            assert!(layout.seat_with_id(15).is_none());
+        }
+    }
 }
@@ -1,75 +1,469 @@
 use advent_of_code_2020::Input;
-use std::collections::HashSet;
-use std::error;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::hint::black_box;
+use std::io::{self, Write};
+use std::iter::FromIterator;
 use std::str::FromStr;
+use std::time::Instant;
+use std::{env, error, fmt};
 use thiserror::Error;
 
-#[derive(Debug, Error)]
-#[error("Invalid answer")]
-struct InvalidAnswer;
+/// Questions that can be answered
+const QUESTIONS: &str = "abcdefghijklmnopqrstuvwxyz";
+
+#[derive(Debug, PartialEq, Eq, Error)]
+enum InvalidAnswer {
+    #[error("Unknown question `{ch}` at line {line}, column {column}")]
+    UnknownQuestion {
+        ch: char,
+        line: usize,
+        column: usize,
+    },
+    #[error("Duplicate answer `{ch}` at line {line}, column {column}")]
+    DuplicateAnswer {
+        ch: char,
+        line: usize,
+        column: usize,
+    },
+}
+
+/// How to treat the characters of a group's answers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParseMode<'a> {
+    /// Whitespace is skipped, any other character counts as answer
+    Lenient,
+    /// Only the given questions may be answered, each at most once per person
+    Strict(&'a str),
+}
+
+/// Set of answered questions, with `a` to `z` kept as bits and any other characters in a
+/// fallback set
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct AnswerSet {
+    bits: u32,
+    other: BTreeSet<char>,
+}
+
+impl AnswerSet {
+    fn insert(&mut self, ch: char) {
+        if ch.is_ascii_lowercase() {
+            self.bits |= 1 << (ch as u32 - 'a' as u32);
+        } else {
+            self.other.insert(ch);
+        }
+    }
+
+    fn contains(&self, ch: char) -> bool {
+        if ch.is_ascii_lowercase() {
+            self.bits & 1 << (ch as u32 - 'a' as u32) != 0
+        } else {
+            self.other.contains(&ch)
+        }
+    }
+
+    fn union_with(&mut self, other: &Self) {
+        self.bits |= other.bits;
+        self.other.extend(&other.other);
+    }
+
+    fn intersect_with(&mut self, other: &Self) {
+        self.bits &= other.bits;
+        self.other.retain(|ch| other.other.contains(ch));
+    }
+
+    fn len(&self) -> usize {
+        self.bits.count_ones() as usize + self.other.len()
+    }
+
+    /// Answered questions in order
+    fn chars(&self) -> Vec<char> {
+        let mut chars: Vec<char> = ('a'..='z')
+            .filter(|ch| self.contains(*ch))
+            .chain(self.other.iter().copied())
+            .collect();
+        chars.sort_unstable();
+        chars
+    }
+}
+
+impl FromIterator<char> for AnswerSet {
+    fn from_iter<I: IntoIterator<Item = char>>(iter: I) -> Self {
+        let mut answers = Self::default();
+        for ch in iter {
+            answers.insert(ch);
+        }
+        answers
+    }
+}
 
 #[derive(Debug)]
 struct GroupAnswers {
-    any: HashSet<char>,
-    every: HashSet<char>,
+    /// Answers of each person in the group
+    people: Vec<AnswerSet>,
 }
 
 impl FromStr for GroupAnswers {
     type Err = InvalidAnswer;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut lines = s.lines();
-        let mut any = HashSet::new();
-        let mut every = HashSet::new();
-        if let Some(line) = lines.next() {
-            let answers: String = line.chars().filter(|ch| !ch.is_whitespace()).collect();
-            any.extend(answers.chars());
-            every.extend(answers.chars());
-        }
-        for line in lines {
-            let answers: String = line.chars().filter(|ch| !ch.is_whitespace()).collect();
-            any.extend(answers.chars());
-            every.retain(|ch| answers.contains(*ch));
-        }
-        Ok(Self { any, every })
+        Self::parse(s, ParseMode::Lenient)
     }
 }
 
 impl GroupAnswers {
+    /// Parse answers of a group, one line per person (line and column in errors count from 1)
+    fn parse(s: &str, mode: ParseMode<'_>) -> Result<Self, InvalidAnswer> {
+        let people = s
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                let mut answers = AnswerSet::default();
+                for (j, ch) in line.chars().enumerate() {
+                    let (line, column) = (i + 1, j + 1);
+                    match mode {
+                        ParseMode::Lenient if ch.is_whitespace() => (),
+                        ParseMode::Strict(questions) if !questions.contains(ch) => {
+                            return Err(InvalidAnswer::UnknownQuestion { ch, line, column });
+                        }
+                        ParseMode::Strict(_questions) if answers.contains(ch) => {
+                            return Err(InvalidAnswer::DuplicateAnswer { ch, line, column });
+                        }
+                        _ => answers.insert(ch),
+                    }
+                }
+                Ok(answers)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { people })
+    }
+
+    /// Number of people in the group
+    fn size(&self) -> usize {
+        self.people.len()
+    }
+
+    /// Number of people who answered each question, for every question answered by anyone
+    fn answer_counts(&self) -> BTreeMap<char, usize> {
+        let mut counts = BTreeMap::new();
+        for answers in &self.people {
+            for ch in answers.chars() {
+                *counts.entry(ch).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    /// Questions whose number of answering people satisfies the given quorum
+    fn answered_by(&self, quorum: impl Fn(usize) -> bool) -> AnswerSet {
+        self.answer_counts()
+            .into_iter()
+            .filter(|(_ch, count)| quorum(*count))
+            .map(|(ch, _count)| ch)
+            .collect()
+    }
+
+    /// Questions answered by at least k people (uses plain union or intersection if k is
+    /// one or the group size)
+    fn answered_by_at_least(&self, k: usize) -> AnswerSet {
+        let mut people = self.people.iter();
+        if k <= 1 {
+            people.fold(AnswerSet::default(), |mut any, answers| {
+                any.union_with(answers);
+                any
+            })
+        } else if k == self.size() {
+            let every = people.next().cloned().unwrap_or_default();
+            people.fold(every, |mut every, answers| {
+                every.intersect_with(answers);
+                every
+            })
+        } else {
+            self.answered_by(|count| count >= k)
+        }
+    }
+
+    /// Questions answered by exactly one person
+    fn answered_by_exactly_one(&self) -> AnswerSet {
+        self.answered_by(|count| count == 1)
+    }
+
+    /// Questions answered by more than half of the group
+    fn answered_by_majority(&self) -> AnswerSet {
+        self.answered_by(|count| count * 2 > self.size())
+    }
+
     #[allow(dead_code)]
     fn any_answers(&self) -> String {
-        let mut answers: Vec<char> = self.any.iter().copied().collect();
-        answers.sort_unstable();
-        answers.iter().collect()
+        self.answered_by_at_least(1).chars().iter().collect()
     }
 
     fn any_answer_count(&self) -> usize {
-        self.any.len()
+        self.answered_by_at_least(1).len()
     }
 
     #[allow(dead_code)]
     fn every_answers(&self) -> String {
-        let mut answers: Vec<char> = self.every.iter().copied().collect();
-        answers.sort_unstable();
-        answers.iter().collect()
+        self.answered_by_at_least(self.size())
+            .chars()
+            .iter()
+            .collect()
     }
 
     fn every_answer_count(&self) -> usize {
-        self.every.len()
+        self.answered_by_at_least(self.size()).len()
+    }
+}
+
+/// Statistics over the answers of all groups
+#[derive(Debug, Default)]
+struct SurveyReport {
+    groups: usize,
+    people: usize,
+    /// Number of groups in which anyone answered each question
+    group_frequency: BTreeMap<char, usize>,
+    /// Number of people who answered each question
+    individual_frequency: BTreeMap<char, usize>,
+    /// Number of groups of each size
+    group_sizes: BTreeMap<usize, usize>,
+    /// Number of people who answered both questions of each pair
+    pair_frequency: BTreeMap<(char, char), usize>,
+}
+
+impl SurveyReport {
+    fn new(groups: &[GroupAnswers]) -> Self {
+        let mut report = Self::default();
+        for group in groups {
+            report.groups += 1;
+            report.people += group.size();
+            *report.group_sizes.entry(group.size()).or_default() += 1;
+            for (ch, count) in group.answer_counts() {
+                *report.group_frequency.entry(ch).or_default() += 1;
+                *report.individual_frequency.entry(ch).or_default() += count;
+            }
+            for answers in &group.people {
+                let chars = answers.chars();
+                for (i, a) in chars.iter().enumerate() {
+                    for b in &chars[i + 1..] {
+                        *report.pair_frequency.entry((*a, *b)).or_default() += 1;
+                    }
+                }
+            }
+        }
+        report
+    }
+
+    /// Question answered by the most people, with its number of people
+    fn most_popular(&self) -> Option<(char, usize)> {
+        let max = self.individual_frequency.iter().max_by_key(|(_ch, n)| **n);
+        max.map(|(ch, n)| (*ch, *n))
+    }
+
+    /// Question answered by the fewest people, with its number of people
+    fn least_popular(&self) -> Option<(char, usize)> {
+        let min = self.individual_frequency.iter().min_by_key(|(_ch, n)| **n);
+        min.map(|(ch, n)| (*ch, *n))
+    }
+
+    /// Correlation (phi coefficient) between people answering the two given questions,
+    /// if both questions were answered by some but not all people
+    fn correlation(&self, a: char, b: char) -> Option<f64> {
+        let n = self.people as f64;
+        let na = *self.individual_frequency.get(&a)? as f64;
+        let nb = *self.individual_frequency.get(&b)? as f64;
+        let pair = if a < b { (a, b) } else { (b, a) };
+        let nab = self.pair_frequency.get(&pair).copied().unwrap_or(0) as f64;
+        let denominator = (na * (n - na) * nb * (n - nb)).sqrt();
+        Some((n * nab - na * nb) / denominator).filter(|_| denominator > 0.0)
+    }
+
+    /// Correlations of all pairs of answered questions, strongest first
+    fn correlations(&self) -> Vec<(char, char, f64)> {
+        let questions: Vec<char> = self.individual_frequency.keys().copied().collect();
+        let mut correlations: Vec<_> = questions
+            .iter()
+            .enumerate()
+            .flat_map(|(i, a)| questions[i + 1..].iter().map(move |b| (*a, *b)))
+            .filter_map(|(a, b)| self.correlation(a, b).map(|phi| (a, b, phi)))
+            .collect();
+        correlations.sort_by(|x, y| y.2.abs().total_cmp(&x.2.abs()));
+        correlations
+    }
+
+    /// Write per-question frequencies and correlations with every other question as CSV
+    fn write_csv(&self, w: &mut impl Write) -> io::Result<()> {
+        let questions: Vec<char> = self.individual_frequency.keys().copied().collect();
+        write!(w, "question,groups,people")?;
+        for b in &questions {
+            write!(w, ",correlation_{}", b)?;
+        }
+        writeln!(w)?;
+        for a in &questions {
+            write!(
+                w,
+                "{},{},{}",
+                a, self.group_frequency[a], self.individual_frequency[a]
+            )?;
+            for b in &questions {
+                match self.correlation(*a, *b).filter(|_| a != b) {
+                    Some(phi) => write!(w, ",{:.4}", phi)?,
+                    None => write!(w, ",")?,
+                }
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    /// Write group size distribution and the most and least popular question as CSV, one
+    /// statistic per line
+    fn write_summary_csv(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "statistic,key,value")?;
+        for (size, groups) in &self.group_sizes {
+            writeln!(w, "group_size,{},{}", size, groups)?;
+        }
+        if let Some((ch, people)) = self.most_popular() {
+            writeln!(w, "most_popular,{},{}", ch, people)?;
+        }
+        if let Some((ch, people)) = self.least_popular() {
+            writeln!(w, "least_popular,{},{}", ch, people)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SurveyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Groups: {}, people: {}", self.groups, self.people)?;
+        writeln!(f, "Question  Groups  People")?;
+        for (ch, people) in &self.individual_frequency {
+            writeln!(
+                f,
+                "{:>8}  {:>6}  {:>6}",
+                ch, self.group_frequency[ch], people
+            )?;
+        }
+        writeln!(f, "Group size  Groups")?;
+        for (size, groups) in &self.group_sizes {
+            writeln!(f, "{:>10}  {:>6}", size, groups)?;
+        }
+        if let Some((ch, people)) = self.most_popular() {
+            writeln!(f, "Most popular question: {} ({} people)", ch, people)?;
+        }
+        if let Some((ch, people)) = self.least_popular() {
+            writeln!(f, "Least popular question: {} ({} people)", ch, people)?;
+        }
+        writeln!(f, "Strongest correlations:")?;
+        for (a, b, phi) in self.correlations().iter().take(5) {
+            writeln!(f, "{:>8} {}  {:>7.4}", a, b, phi)?;
+        }
+        Ok(())
+    }
+}
+
+/// Group answer counts using hash sets of characters, for comparison
+fn hash_set_answer_counts(s: &str) -> (usize, usize) {
+    let mut lines = s.lines();
+    let mut any = HashSet::new();
+    let mut every = HashSet::new();
+    if let Some(line) = lines.next() {
+        let answers: String = line.chars().filter(|ch| !ch.is_whitespace()).collect();
+        any.extend(answers.chars());
+        every.extend(answers.chars());
     }
+    for line in lines {
+        let answers: String = line.chars().filter(|ch| !ch.is_whitespace()).collect();
+        any.extend(answers.chars());
+        every.retain(|ch| answers.contains(*ch));
+    }
+    (any.len(), every.len())
+}
+
+/// Compare bit set and hash set group answers on many generated groups
+fn bench() -> Result<(), InvalidAnswer> {
+    let groups: Vec<String> = (0..200_000_u32)
+        .map(|i| {
+            (0..i % 5 + 1)
+                .map(|person| {
+                    ('a'..='z')
+                        .filter(|ch| (i + person * 7 + *ch as u32 * 13) % 3 == 1)
+                        .collect::<String>()
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect();
+
+    let start = Instant::now();
+    let mut sums = (0, 0);
+    for group in &groups {
+        let answers: GroupAnswers = black_box(group).parse()?;
+        sums.0 += answers.any_answer_count();
+        sums.1 += answers.every_answer_count();
+    }
+    println!("Bit sets: {:?} in {:?}", sums, start.elapsed());
+
+    let start = Instant::now();
+    let mut sums = (0, 0);
+    for group in &groups {
+        let (any, every) = hash_set_answer_counts(black_box(group));
+        sums.0 += any;
+        sums.1 += every;
+    }
+    println!("Hash sets: {:?} in {:?}", sums, start.elapsed());
+
+    Ok(())
 }
 
 fn main() -> Result<(), Box<dyn error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--bench") {
+        bench()?;
+        return Ok(());
+    }
+
+    let mode = if args.iter().any(|arg| arg == "--strict") {
+        ParseMode::Strict(QUESTIONS)
+    } else {
+        ParseMode::Lenient
+    };
     let answers: Vec<GroupAnswers> = Input::day(6)?
-        .iter_parsed_blocks()
+        .iter_blocks()
+        .enumerate()
+        .map(|(i, block)| -> Result<_, Box<dyn error::Error>> {
+            GroupAnswers::parse(&block?, mode).map_err(|e| format!("Group {}: {}", i + 1, e).into())
+        })
         .collect::<Result<_, _>>()?;
 
+    // CSV export: --csv [summary]
+    if let Some(pos) = args.iter().position(|arg| arg == "--csv") {
+        let report = SurveyReport::new(&answers);
+        let mut out = io::stdout().lock();
+        match args.get(pos + 1).map(String::as_str) {
+            Some("summary") => report.write_summary_csv(&mut out)?,
+            _ => report.write_csv(&mut out)?,
+        }
+        return Ok(());
+    }
+
     let sum: usize = answers.iter().map(GroupAnswers::any_answer_count).sum();
     println!("Sum of group answer any counts: {}", sum);
 
     let sum: usize = answers.iter().map(GroupAnswers::every_answer_count).sum();
     println!("Sum of group answer every counts: {}", sum);
 
+    let sum: usize = answers.iter().map(|a| a.answered_by_majority().len()).sum();
+    println!("Sum of group answer majority counts: {}", sum);
+
+    let sum: usize = answers
+        .iter()
+        .map(|a| a.answered_by_exactly_one().len())
+        .sum();
+    println!("Sum of group answer exactly one counts: {}", sum);
+
+    if args.iter().any(|arg| arg == "--report") {
+        print!("{}", SurveyReport::new(&answers));
+    }
+
     Ok(())
 }
 
@@ -127,4 +521,123 @@ mod tests {
         assert_eq!(answers[5].every_answers(), "b");
         assert_eq!(answers[5].every_answer_count(), 1);
     }
+
+    #[test]
+    fn quorum() {
+        let answers: GroupAnswers = "abcd\nabe\nacf\naeg".parse().unwrap();
+        assert_eq!(answers.size(), 4);
+        assert_eq!(answers.answer_counts()[&'a'], 4);
+        assert_eq!(answers.answer_counts()[&'e'], 2);
+        assert_eq!(
+            answers.answered_by_at_least(2).chars(),
+            ['a', 'b', 'c', 'e']
+        );
+        assert_eq!(answers.answered_by_at_least(4).chars(), ['a']);
+        assert_eq!(answers.answered_by_at_least(5).chars(), []);
+        assert_eq!(answers.answered_by_exactly_one().chars(), ['d', 'f', 'g']);
+        assert_eq!(answers.answered_by_majority().chars(), ['a']);
+        assert_eq!(
+            answers.answered_by_at_least(1),
+            answers.answered_by(|count| count >= 1)
+        );
+    }
+
+    #[test]
+    fn report() {
+        let answers = INPUT
+            .iter()
+            .map(|s| s.parse())
+            .collect::<Result<Vec<GroupAnswers>, _>>()
+            .unwrap();
+        let report = SurveyReport::new(&answers);
+        assert_eq!((report.groups, report.people), (6, 14));
+        assert_eq!(report.group_frequency[&'a'], 5);
+        assert_eq!(report.individual_frequency[&'a'], 11);
+        assert_eq!(report.group_sizes[&1], 2);
+        assert_eq!(report.group_sizes[&4], 1);
+        assert_eq!(report.most_popular(), Some(('a', 11)));
+        assert_eq!(report.least_popular(), Some(('x', 1)));
+        assert_eq!(report.pair_frequency[&('a', 'b')], 5);
+
+        // x and y are each answered by a single, different person
+        let phi = report.correlation('x', 'y').unwrap();
+        assert!((phi + 1.0 / 13.0).abs() < 1e-9);
+        assert_eq!(report.correlation('a', 'b'), report.correlation('b', 'a'));
+        assert_eq!(report.correlations().len(), 15);
+
+        let mut csv = Vec::new();
+        report.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "question,groups,people,correlation_a,correlation_b,correlation_c,correlation_x,correlation_y,correlation_z"
+        );
+        assert_eq!(lines[4], "x,1,1,0.1448,0.2774,0.3203,,-0.0769,-0.0769");
+
+        let mut csv = Vec::new();
+        report.write_summary_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines[0], "statistic,key,value");
+        assert_eq!(lines[1], "group_size,1,2");
+        assert_eq!(lines[lines.len() - 2], "most_popular,a,11");
+        assert_eq!(lines[lines.len() - 1], "least_popular,x,1");
+    }
+
+    #[test]
+    fn strict_parsing() {
+        let strict = ParseMode::Strict(QUESTIONS);
+        for group in &INPUT {
+            assert!(GroupAnswers::parse(group, strict).is_ok());
+        }
+        assert_eq!(
+            GroupAnswers::parse("abc\nab1", strict).unwrap_err(),
+            InvalidAnswer::UnknownQuestion {
+                ch: '1',
+                line: 2,
+                column: 3
+            }
+        );
+        assert_eq!(
+            GroupAnswers::parse("abcB", strict).unwrap_err(),
+            InvalidAnswer::UnknownQuestion {
+                ch: 'B',
+                line: 1,
+                column: 4
+            }
+        );
+        assert_eq!(
+            GroupAnswers::parse("a\nbcdc", strict).unwrap_err(),
+            InvalidAnswer::DuplicateAnswer {
+                ch: 'c',
+                line: 2,
+                column: 4
+            }
+        );
+        assert!(GroupAnswers::parse("a b", strict).is_err());
+        assert!(GroupAnswers::parse("xyz", ParseMode::Strict("abc")).is_err());
+        assert_eq!(
+            GroupAnswers::parse("a bb1", ParseMode::Lenient)
+                .unwrap()
+                .any_answers(),
+            "1ab"
+        );
+    }
+
+    #[test]
+    fn answer_sets() {
+        let answers: GroupAnswers = "abcZ\nbcZ1\nbc1Z".parse().unwrap();
+        assert_eq!(answers.any_answers(), "1Zabc");
+        assert_eq!(answers.every_answers(), "Zbc");
+        assert_eq!(answers.answered_by_at_least(3).bits, 0b110);
+
+        for group in &INPUT {
+            let answers: GroupAnswers = group.parse().unwrap();
+            assert_eq!(
+                hash_set_answer_counts(group),
+                (answers.any_answer_count(), answers.every_answer_count())
+            );
+        }
+    }
 }
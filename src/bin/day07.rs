@@ -1,29 +1,193 @@
 use advent_of_code_2020::Input;
-use lazy_static::lazy_static;
-use regex::Regex;
-use std::collections::{HashMap, HashSet};
-use std::error;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::iter::{self, FromIterator};
+use std::rc::Rc;
 use std::str::FromStr;
+use std::{env, error, fmt, io};
 use thiserror::Error;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-struct Bag(String);
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Bag(Rc<str>);
 
 impl From<&str> for Bag {
     fn from(s: &str) -> Self {
-        Self(s.to_string())
+        Self(s.into())
     }
 }
 
 impl PartialEq<&str> for Bag {
     fn eq(&self, other: &&str) -> bool {
-        self.0 == *other
+        &*self.0 == *other
     }
 }
 
-#[derive(Debug, Error)]
-#[error("Invalid rule")]
-struct InvalidRule;
+impl fmt::Display for Bag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Rule text not following the grammar, pinpointing the offending token
+#[derive(Debug, PartialEq, Eq, Error)]
+#[error("Invalid rule: expected {expected} at column {column}, found {found}")]
+struct InvalidRule {
+    /// Column of the token (1-based byte offset)
+    column: usize,
+    /// Offending token quoted in backticks, or end of rule
+    found: String,
+    expected: &'static str,
+}
+
+/// Word, `,` or `.` of a rule and its column
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+/// Split rule text into whitespace separated words and punctuation
+fn tokenize(s: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut word_start = None;
+    for (i, ch) in s.char_indices() {
+        let is_punctuation = ch == ',' || ch == '.';
+        if ch.is_whitespace() || is_punctuation {
+            if let Some(start) = word_start.take() {
+                tokens.push(Token {
+                    text: &s[start..i],
+                    column: start + 1,
+                });
+            }
+            if is_punctuation {
+                tokens.push(Token {
+                    text: &s[i..i + 1],
+                    column: i + 1,
+                });
+            }
+        } else if word_start.is_none() {
+            word_start = Some(i);
+        }
+    }
+    if let Some(start) = word_start {
+        tokens.push(Token {
+            text: &s[start..],
+            column: start + 1,
+        });
+    }
+    tokens
+}
+
+/// Parser for the rule grammar:
+/// `COLOR bags contain (no other bags | COUNT COLOR bag[s] {, COUNT COLOR bag[s]}) .`
+struct RuleParser<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    end_column: usize,
+}
+
+impl<'a> RuleParser<'a> {
+    fn new(s: &'a str) -> Self {
+        Self {
+            tokens: tokenize(s),
+            pos: 0,
+            end_column: s.trim_end().len() + 1,
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|token| token.text)
+    }
+
+    /// Error for the current token
+    fn error(&self, expected: &'static str) -> InvalidRule {
+        match self.tokens.get(self.pos) {
+            Some(token) => InvalidRule {
+                column: token.column,
+                found: format!("`{}`", token.text),
+                expected,
+            },
+            None => InvalidRule {
+                column: self.end_column,
+                found: "end of rule".to_string(),
+                expected,
+            },
+        }
+    }
+
+    fn expect(&mut self, text: &'static str, expected: &'static str) -> Result<(), InvalidRule> {
+        if self.peek() == Some(text) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(expected))
+        }
+    }
+
+    /// Color of any number of words, up to the following `bag` or `bags`
+    fn color(&mut self) -> Result<Bag, InvalidRule> {
+        let start = self.pos;
+        while let Some(word) = self.peek() {
+            if word == "bag" || word == "bags" || word == "," || word == "." {
+                break;
+            }
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(self.error("color"));
+        }
+        let words: Vec<_> = self.tokens[start..self.pos]
+            .iter()
+            .map(|token| token.text)
+            .collect();
+        Ok(Bag(words.join(" ").into()))
+    }
+
+    fn count(&mut self) -> Result<usize, InvalidRule> {
+        let count = self
+            .peek()
+            .filter(|word| word.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|word| word.parse().ok())
+            .ok_or_else(|| self.error("count"))?;
+        self.pos += 1;
+        Ok(count)
+    }
+
+    fn rule(&mut self) -> Result<Rule, InvalidRule> {
+        let bag = self.color()?;
+        self.expect("bags", "`bags`")?;
+        self.expect("contain", "`contain`")?;
+        let mut contains = Vec::new();
+        if self.peek() == Some("no") {
+            self.pos += 1;
+            self.expect("other", "`other`")?;
+            self.expect("bags", "`bags`")?;
+            self.expect(".", "`.`")?;
+        } else {
+            loop {
+                let count = self.count()?;
+                let inner = self.color()?;
+                match self.peek() {
+                    Some("bag") | Some("bags") => self.pos += 1,
+                    _ => return Err(self.error("`bag` or `bags`")),
+                }
+                contains.push((count, inner));
+                match self.peek() {
+                    Some(",") => self.pos += 1,
+                    Some(".") => {
+                        self.pos += 1;
+                        break;
+                    }
+                    _ => return Err(self.error("`,` or `.`")),
+                }
+            }
+        }
+        if self.pos < self.tokens.len() {
+            return Err(self.error("end of rule"));
+        }
+        Ok(Rule { bag, contains })
+    }
+}
 
 #[derive(Debug)]
 struct Rule {
@@ -35,95 +199,1009 @@ impl FromStr for Rule {
     type Err = InvalidRule;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        lazy_static! {
-            static ref RE_RULE: Regex = Regex::new(
-                r#"^(\w+ \w+) bags contain (no other bags|(\d \w+ \w+ bags?(, )?)+)\.$"#
-            )
-            .unwrap();
-            static ref RE_CONTAINS: Regex = Regex::new(r#"(\d) (\w+ \w+) bags?"#).unwrap();
-        }
-
-        let caps = RE_RULE.captures(s).ok_or(InvalidRule)?;
-        let bag = Bag::from(caps.get(1).ok_or(InvalidRule)?.as_str());
-        let s2 = caps.get(2).ok_or(InvalidRule)?.as_str();
-        let contains: Vec<_> = RE_CONTAINS
-            .captures_iter(s2)
-            .map(|caps| {
-                let count = caps.get(1).and_then(|m| m.as_str().parse::<usize>().ok());
-                let bag = caps.get(2).map(|m| Bag::from(m.as_str()));
-                if let (Some(c), Some(b)) = (count, bag) {
-                    Ok((c, b))
-                } else {
-                    Err(InvalidRule)
-                }
-            })
-            .collect::<Result<_, _>>()?;
-        Ok(Self { bag, contains })
+        RuleParser::new(s).rule()
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bags contain ", self.bag)?;
+        if self.contains.is_empty() {
+            return f.write_str("no other bags.");
+        }
+        for (i, (count, bag)) in self.contains.iter().enumerate() {
+            let separator = if i > 0 { ", " } else { "" };
+            let plural = if *count == 1 { "" } else { "s" };
+            write!(f, "{}{} {} bag{}", separator, count, bag, plural)?;
+        }
+        f.write_str(".")
+    }
+}
+
+/// Bags that (transitively) contain themselves, as containment path from a bag back to itself
+#[derive(Debug, Error)]
+struct CyclicRules(Vec<Bag>);
+
+impl fmt::Display for CyclicRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path: Vec<_> = self.0.iter().map(|bag| &*bag.0).collect();
+        write!(f, "Cyclic rules: {}", path.join(" -> "))
+    }
+}
+
+/// Number of bags inside a bag exceeds the range of the number type it is counted with
+#[derive(Debug, Error)]
+#[error("Bag count overflow in {0} bag")]
+struct CountOverflow(Bag);
+
+/// Bag that has no rule in the rule set
+#[derive(Debug, Error)]
+#[error("Unknown bag {0}")]
+struct UnknownBag(Bag);
+
+/// Failure to count the bags inside a bag
+#[derive(Debug, Error)]
+enum CountError {
+    #[error(transparent)]
+    Unknown(#[from] UnknownBag),
+    #[error(transparent)]
+    Overflow(#[from] CountOverflow),
+}
+
+/// Number type bags can be counted with
+trait BagCount: Sized {
+    fn zero() -> Self;
+    fn one() -> Self;
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    fn checked_mul(&self, factor: usize) -> Option<Self>;
+}
+
+impl BagCount for usize {
+    fn zero() -> Self {
+        0
+    }
+
+    fn one() -> Self {
+        1
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        usize::checked_add(*self, *other)
+    }
+
+    fn checked_mul(&self, factor: usize) -> Option<Self> {
+        usize::checked_mul(*self, factor)
     }
 }
 
+const BIG_COUNT_BASE: u64 = 1_000_000_000;
+
+/// Arbitrary-precision bag count, stored as little-endian base 10^9 digits without
+/// leading zeros
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BigCount(Vec<u32>);
+
+impl BagCount for BigCount {
+    fn zero() -> Self {
+        Self(vec![])
+    }
+
+    fn one() -> Self {
+        Self(vec![1])
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        let len = self.0.len().max(other.0.len());
+        let mut digits = Vec::with_capacity(len + 1);
+        let mut carry = 0;
+        for i in 0..len {
+            let a = self.0.get(i).copied().unwrap_or(0) as u64;
+            let b = other.0.get(i).copied().unwrap_or(0) as u64;
+            let sum = a + b + carry;
+            digits.push((sum % BIG_COUNT_BASE) as u32);
+            carry = sum / BIG_COUNT_BASE;
+        }
+        if carry > 0 {
+            digits.push(carry as u32);
+        }
+        Some(Self(digits))
+    }
+
+    fn checked_mul(&self, factor: usize) -> Option<Self> {
+        if factor == 0 {
+            return Some(Self::zero());
+        }
+        let base = BIG_COUNT_BASE as u128;
+        let mut digits = Vec::with_capacity(self.0.len() + 3);
+        let mut carry = 0;
+        for &digit in &self.0 {
+            let product = digit as u128 * factor as u128 + carry;
+            digits.push((product % base) as u32);
+            carry = product / base;
+        }
+        while carry > 0 {
+            digits.push((carry % base) as u32);
+            carry /= base;
+        }
+        Some(Self(digits))
+    }
+}
+
+impl fmt::Display for BigCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut digits = self.0.iter().rev();
+        match digits.next() {
+            Some(digit) => write!(f, "{}", digit)?,
+            None => return f.write_str("0"),
+        }
+        for digit in digits {
+            write!(f, "{:09}", digit)?;
+        }
+        Ok(())
+    }
+}
+
+/// Bags included in a graph export, relative to the query bag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Relatives {
+    All,
+    Ancestors,
+    Descendants,
+}
+
+/// Quote a bag color as DOT identifier
+fn dot_id(bag: &Bag) -> String {
+    format!("\"{}\"", bag.0.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Query arguments not matching any known query
+#[derive(Debug, Error)]
+#[error("Invalid query, expected {0}")]
+struct InvalidQuery(&'static str);
+
+const QUERY_USAGE: &str = "`containers BAG`, `paths OUTER INNER`, `shortest OUTER INNER`, \
+                           `longest OUTER INNER`, `depth BAG`, `depths`, `both BAG BAG` \
+                           or `total INNER OUTER`";
+
+/// Question about bag containment
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Query {
+    /// Bags directly containing a bag
+    Containers(Bag),
+    /// All containment paths from an outer to an inner bag
+    Paths(Bag, Bag),
+    /// Containment path with the fewest bags from an outer to an inner bag
+    ShortestPath(Bag, Bag),
+    /// Containment path with the most bags from an outer to an inner bag
+    LongestPath(Bag, Bag),
+    /// Maximum nesting depth of bags inside a bag
+    Depth(Bag),
+    /// Nesting depth of every bag
+    Depths,
+    /// Bags (transitively) containing both bags
+    ContainingBoth(Bag, Bag),
+    /// Total number of inner bags inside an outer bag
+    Total { inner: Bag, outer: Bag },
+}
+
+impl Query {
+    /// Parse query from command line arguments, e.g. `paths "light red" "shiny gold"`
+    fn from_args(args: &[String]) -> Result<Self, InvalidQuery> {
+        let bags: Vec<Bag> = args.iter().skip(1).map(|arg| Bag::from(&arg[..])).collect();
+        match (args.first().map(String::as_str), &bags[..]) {
+            (Some("containers"), [bag]) => Ok(Self::Containers(bag.clone())),
+            (Some("paths"), [outer, inner]) => Ok(Self::Paths(outer.clone(), inner.clone())),
+            (Some("shortest"), [outer, inner]) => {
+                Ok(Self::ShortestPath(outer.clone(), inner.clone()))
+            }
+            (Some("longest"), [outer, inner]) => {
+                Ok(Self::LongestPath(outer.clone(), inner.clone()))
+            }
+            (Some("depth"), [bag]) => Ok(Self::Depth(bag.clone())),
+            (Some("depths"), []) => Ok(Self::Depths),
+            (Some("both"), [a, b]) => Ok(Self::ContainingBoth(a.clone(), b.clone())),
+            (Some("total"), [inner, outer]) => Ok(Self::Total {
+                inner: inner.clone(),
+                outer: outer.clone(),
+            }),
+            _ => Err(InvalidQuery(QUERY_USAGE)),
+        }
+    }
+}
+
+/// Containment path from an outer bag down to an inner bag
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ContainmentPath {
+    bags: Vec<Bag>,
+    /// Number of inner bags along this path, i.e. the product of counts
+    multiplicity: usize,
+}
+
+impl fmt::Display for ContainmentPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bags: Vec<_> = self.bags.iter().map(|bag| &*bag.0).collect();
+        f.write_str(&bags.join(" -> "))
+    }
+}
+
+/// Query result with named columns
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Table {
+    header: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    fn new(header: Vec<&'static str>) -> Self {
+        Self {
+            header,
+            rows: Vec::new(),
+        }
+    }
+
+    fn push<T: fmt::Display, I: IntoIterator<Item = T>>(&mut self, row: I) {
+        self.rows
+            .push(row.into_iter().map(|cell| cell.to_string()).collect());
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut widths: Vec<usize> = self.header.iter().map(|h| h.len()).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let header = self.header.iter().map(|h| h.to_string()).collect();
+        for row in std::iter::once(&header).chain(&self.rows) {
+            let cells: Vec<_> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect();
+            writeln!(f, "{}", cells.join("  ").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// Compact identifier of an interned bag color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct BagId(u32);
+
+impl BagId {
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Interner mapping bag colors to compact ids, handed out in order of first appearance.
+/// Names and lookup keys share a single allocation per color.
+#[derive(Debug, Default)]
+struct BagInterner {
+    names: Vec<Bag>,
+    ids: HashMap<Rc<str>, BagId>,
+}
+
+impl BagInterner {
+    fn intern(&mut self, bag: Bag) -> BagId {
+        if let Some(id) = self.ids.get(&bag.0) {
+            return *id;
+        }
+        let id = BagId(self.names.len() as u32);
+        self.ids.insert(Rc::clone(&bag.0), id);
+        self.names.push(bag);
+        id
+    }
+
+    fn get(&self, bag: &str) -> Option<BagId> {
+        self.ids.get(bag).copied()
+    }
+
+    fn name(&self, id: BagId) -> &Bag {
+        &self.names[id.index()]
+    }
+
+    fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    fn ids(&self) -> impl Iterator<Item = BagId> {
+        (0..self.names.len() as u32).map(BagId)
+    }
+}
+
+/// Set of rules, which is guaranteed to be free of cycles and to have a rule for every
+/// bag. Bag colors are interned and the containment graph is stored as vectors indexed
+/// by bag id.
 #[derive(Debug)]
 struct RuleSet {
-    contains: HashMap<Bag, Vec<(usize, Bag)>>,
-    contained: HashMap<Bag, HashSet<Bag>>,
+    bags: BagInterner,
+    contains: Vec<Vec<(usize, BagId)>>,
+    contained: Vec<Vec<BagId>>,
 }
 
-impl From<Vec<Rule>> for RuleSet {
-    fn from(rules: Vec<Rule>) -> Self {
-        let mut contains = HashMap::new();
-        let mut contained = HashMap::new();
-        for rule in rules {
-            for (_c, b) in &rule.contains {
-                contained
-                    .entry(b.clone())
-                    .or_insert_with(HashSet::new)
-                    .insert(rule.bag.clone());
-            }
-            contains.insert(rule.bag, rule.contains);
+impl fmt::Display for RuleSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for rule in self.rules() {
+            writeln!(f, "{}", rule)?;
         }
+        Ok(())
+    }
+}
+
+/// Problems found when building a rule set
+#[derive(Debug, Default, Error)]
+struct InvalidRuleSet {
+    /// Bags without a rule of their own, with the bag referencing them
+    undefined: Vec<(Bag, Bag)>,
+    /// Bags with more than one rule
+    duplicate: Vec<Bag>,
+    cycle: Option<CyclicRules>,
+}
+
+impl InvalidRuleSet {
+    /// Whether no problem was found at all
+    fn is_empty(&self) -> bool {
+        self.undefined.is_empty() && self.duplicate.is_empty() && self.cycle.is_none()
+    }
+
+    /// Human readable descriptions of every problem found
+    fn reasons(&self) -> Vec<String> {
+        let undefined = self
+            .undefined
+            .iter()
+            .map(|(outer, inner)| format!("undefined `{}` in `{}`", inner, outer));
+        let duplicate = self
+            .duplicate
+            .iter()
+            .map(|bag| format!("duplicate `{}`", bag));
+        let cycle = self.cycle.iter().map(|cycle| cycle.to_string());
+        undefined.chain(duplicate).chain(cycle).collect()
+    }
+}
+
+impl fmt::Display for InvalidRuleSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid rule set: {}", self.reasons().join(", "))
+    }
+}
+
+/// Collects rules and validates them as a whole before building a rule set
+#[derive(Debug, Default)]
+struct RuleSetBuilder {
+    rules: Vec<Rule>,
+}
+
+impl FromIterator<Rule> for RuleSetBuilder {
+    fn from_iter<T: IntoIterator<Item = Rule>>(iter: T) -> Self {
         Self {
-            contains,
+            rules: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl RuleSetBuilder {
+    /// Build the rule set, reporting every problem found
+    fn build(self) -> Result<RuleSet, InvalidRuleSet> {
+        let mut problems = InvalidRuleSet::default();
+        let mut bags = BagInterner::default();
+        let mut defined: Vec<Option<Vec<(usize, BagId)>>> = Vec::new();
+        for rule in self.rules {
+            let outer = bags.intern(rule.bag);
+            let inner: Vec<_> = rule
+                .contains
+                .into_iter()
+                .map(|(count, bag)| (count, bags.intern(bag)))
+                .collect();
+            defined.resize(bags.len(), None);
+            if defined[outer.index()].is_some() {
+                if !problems.duplicate.contains(bags.name(outer)) {
+                    problems.duplicate.push(bags.name(outer).clone());
+                }
+                continue;
+            }
+            defined[outer.index()] = Some(inner);
+        }
+
+        let mut contained = vec![Vec::new(); bags.len()];
+        for (outer, inner) in bags.ids().zip(&defined) {
+            for &(_count, bag) in inner.iter().flatten() {
+                // A bag listed twice in a rule would be pushed right after itself
+                if contained[bag.index()].last() != Some(&outer) {
+                    contained[bag.index()].push(outer);
+                }
+                if defined[bag.index()].is_none() {
+                    let names = (bags.name(outer).clone(), bags.name(bag).clone());
+                    problems.undefined.push(names);
+                }
+            }
+        }
+        problems.undefined.sort();
+        problems.duplicate.sort();
+
+        let rules = RuleSet {
+            bags,
+            contains: defined.into_iter().map(Option::unwrap_or_default).collect(),
             contained,
+        };
+        problems.cycle = rules.find_cycle().map(CyclicRules);
+        if problems.is_empty() {
+            Ok(rules)
+        } else {
+            Err(problems)
         }
     }
 }
 
+impl TryFrom<Vec<Rule>> for RuleSet {
+    type Error = InvalidRuleSet;
+
+    fn try_from(rules: Vec<Rule>) -> Result<Self, Self::Error> {
+        rules.into_iter().collect::<RuleSetBuilder>().build()
+    }
+}
+
 impl RuleSet {
+    fn name(&self, id: BagId) -> &Bag {
+        self.bags.name(id)
+    }
+
+    /// Bags directly inside the given bag, with their counts
+    fn inner(&self, id: BagId) -> &[(usize, BagId)] {
+        &self.contains[id.index()]
+    }
+
+    /// Bags that neither contain nor are contained by any other bag, sorted by color.
+    /// They are harmless for counting, so they are reported as warnings rather than
+    /// making the rule set invalid.
+    fn unreachable(&self) -> Vec<&Bag> {
+        self.sorted_ids()
+            .into_iter()
+            .filter(|&id| self.inner(id).is_empty() && self.contained[id.index()].is_empty())
+            .map(|id| self.name(id))
+            .collect()
+    }
+
+    /// All bag ids, sorted by color
+    fn sorted_ids(&self) -> Vec<BagId> {
+        let mut ids: Vec<BagId> = self.bags.ids().collect();
+        ids.sort_by(|a, b| self.name(*a).cmp(self.name(*b)));
+        ids
+    }
+
+    /// Find a bag that (transitively) contains itself, using a depth-first search that
+    /// keeps the path to the current bag on an explicit stack
+    fn find_cycle(&self) -> Option<Vec<Bag>> {
+        let mut finished = vec![false; self.bags.len()];
+        let mut on_path = vec![false; self.bags.len()];
+        for start in self.sorted_ids() {
+            if finished[start.index()] {
+                continue;
+            }
+            let mut path: Vec<(BagId, usize)> = vec![(start, 0)];
+            on_path[start.index()] = true;
+            while let Some(&(bag, next)) = path.last() {
+                let inner = match self.inner(bag).get(next) {
+                    Some(&(_count, inner)) => inner,
+                    None => {
+                        finished[bag.index()] = true;
+                        on_path[bag.index()] = false;
+                        path.pop();
+                        continue;
+                    }
+                };
+                let last = path.len() - 1;
+                path[last].1 += 1;
+                if on_path[inner.index()] {
+                    let pos = path.iter().position(|(b, _next)| *b == inner)?;
+                    let mut cycle: Vec<Bag> = path[pos..]
+                        .iter()
+                        .map(|(b, _next)| self.name(*b).clone())
+                        .collect();
+                    cycle.push(self.name(inner).clone());
+                    return Some(cycle);
+                }
+                if !finished[inner.index()] {
+                    on_path[inner.index()] = true;
+                    path.push((inner, 0));
+                }
+            }
+        }
+        None
+    }
+
+    /// Bags reachable from the given bags (including themselves) in topological order, so
+    /// every bag comes after all bags it contains
+    fn topological_order<I: IntoIterator<Item = BagId>>(&self, bags: I) -> Vec<BagId> {
+        let mut order = Vec::new();
+        let mut visited = vec![false; self.bags.len()];
+        for bag in bags {
+            if visited[bag.index()] {
+                continue;
+            }
+            visited[bag.index()] = true;
+            let mut stack: Vec<(BagId, usize)> = vec![(bag, 0)];
+            while let Some(&(bag, next)) = stack.last() {
+                match self.inner(bag).get(next) {
+                    Some(&(_count, inner)) => {
+                        let last = stack.len() - 1;
+                        stack[last].1 += 1;
+                        if !visited[inner.index()] {
+                            visited[inner.index()] = true;
+                            stack.push((inner, 0));
+                        }
+                    }
+                    None => {
+                        order.push(bag);
+                        stack.pop();
+                    }
+                }
+            }
+        }
+        order
+    }
+
+    /// Bags containing the given bag, marked by bag id
+    fn containers(&self, bag: BagId, deep: bool) -> Vec<bool> {
+        let mut bags = vec![false; self.bags.len()];
+        let mut queue = vec![bag];
+        while let Some(inner) = queue.pop() {
+            for &outer in &self.contained[inner.index()] {
+                // Only follow bags not seen before, so shared ancestors (or cycles) are
+                // walked once
+                if !bags[outer.index()] {
+                    bags[outer.index()] = true;
+                    if deep {
+                        queue.push(outer);
+                    }
+                }
+            }
+        }
+        bags
+    }
+
+    /// Bag ids marked in the given vector, sorted by color
+    fn marked(&self, bags: &[bool]) -> Vec<BagId> {
+        let mut ids: Vec<BagId> = self.bags.ids().filter(|id| bags[id.index()]).collect();
+        ids.sort_by(|a, b| self.name(*a).cmp(self.name(*b)));
+        ids
+    }
+
     fn which_contains(&self, bag: &str, deep: bool) -> HashSet<Bag> {
-        let mut bags = self
-            .contained
-            .get(&Bag::from(bag))
-            .map(Clone::clone)
-            .unwrap_or_default();
-        if deep {
-            let deep_bags = bags
+        match self.bags.get(bag) {
+            Some(id) => {
+                let containers = self.containers(id, deep);
+                self.bags
+                    .ids()
+                    .filter(|id| containers[id.index()])
+                    .map(|id| self.name(id).clone())
+                    .collect()
+            }
+            None => HashSet::new(),
+        }
+    }
+
+    /// Sum of the counts of the given bags, each multiplied with its total
+    fn sum_counts<T: BagCount>(bags: &[(usize, BagId)], totals: &[T], sum: T) -> Option<T> {
+        bags.iter().try_fold(sum, |sum, &(count, inner)| {
+            totals[inner.index()].checked_mul(count)?.checked_add(&sum)
+        })
+    }
+
+    /// Total number of bags for every bag reachable from the given bag, including the bag
+    /// itself
+    fn totals<T: BagCount>(&self, bag: BagId) -> Result<Vec<T>, CountOverflow> {
+        let mut totals: Vec<T> = self.bags.ids().map(|_| T::zero()).collect();
+        for outer in self.topological_order(iter::once(bag)) {
+            totals[outer.index()] = Self::sum_counts(self.inner(outer), &totals, T::one())
+                .ok_or_else(|| CountOverflow(self.name(outer).clone()))?;
+        }
+        Ok(totals)
+    }
+
+    /// Id of the given bag, which must have a rule
+    fn known(&self, bag: &str) -> Result<BagId, UnknownBag> {
+        self.bags.get(bag).ok_or_else(|| UnknownBag(Bag::from(bag)))
+    }
+
+    /// Number of bags inside the given bag
+    fn count_bags_inside_as<T: BagCount>(&self, bag: &str) -> Result<T, CountError> {
+        let id = self.known(bag)?;
+        let count = Self::sum_counts(self.inner(id), &self.totals(id)?, T::zero())
+            .ok_or_else(|| CountOverflow(self.name(id).clone()))?;
+        Ok(count)
+    }
+
+    /// Number of inner bags directly inside the outer bag, summed over every time the
+    /// rule lists the inner bag
+    fn count_of(&self, outer: BagId, inner: BagId) -> usize {
+        self.inner(outer)
+            .iter()
+            .filter(|(_count, bag)| *bag == inner)
+            .map(|(count, _bag)| *count)
+            .sum()
+    }
+
+    /// Containment path along the given bags from outer to inner, each with the count of
+    /// the edge leading to it. The count of the outermost bag is ignored.
+    fn containment_path(&self, bags: &[(BagId, usize)]) -> Result<ContainmentPath, CountOverflow> {
+        let mut multiplicity: usize = 1;
+        for pair in bags.windows(2) {
+            multiplicity = multiplicity
+                .checked_mul(pair[1].1)
+                .ok_or_else(|| CountOverflow(self.name(pair[0].0).clone()))?;
+        }
+        Ok(ContainmentPath {
+            bags: bags
                 .iter()
-                .map(|bag| self.which_contains(&bag.0, true))
-                .fold(HashSet::new(), |mut db, b| {
-                    db.extend(b);
-                    db
-                });
-            bags.extend(deep_bags);
+                .map(|(id, _count)| self.name(*id).clone())
+                .collect(),
+            multiplicity,
+        })
+    }
+
+    /// All containment paths from the outer bag to the inner bag
+    fn paths(&self, outer: &str, inner: &str) -> Result<Vec<ContainmentPath>, CountOverflow> {
+        let mut paths = Vec::new();
+        let (outer, inner) = match (self.bags.get(outer), self.bags.get(inner)) {
+            (Some(outer), Some(inner)) => (outer, inner),
+            _ => return Ok(paths),
+        };
+        // Only bags that contain the inner bag can lead to it
+        let mut ancestors = self.containers(inner, true);
+        ancestors[inner.index()] = true;
+        if !ancestors[outer.index()] {
+            return Ok(paths);
         }
-        bags
+        // Every bag on the path with the index of its next edge to follow and the count of
+        // the edge taken to reach it, so rules listing a bag twice yield one path per edge
+        let mut path: Vec<(BagId, usize, usize)> = vec![(outer, 0, 1)];
+        while let Some(&(bag, next, _count)) = path.last() {
+            if bag == inner {
+                let bags: Vec<_> = path.iter().map(|(b, _next, count)| (*b, *count)).collect();
+                paths.push(self.containment_path(&bags)?);
+                path.pop();
+                continue;
+            }
+            match self.inner(bag).get(next) {
+                Some(&(count, b)) => {
+                    let last = path.len() - 1;
+                    path[last].1 += 1;
+                    if ancestors[b.index()] {
+                        path.push((b, 0, count));
+                    }
+                }
+                None => {
+                    path.pop();
+                }
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Containment path with the fewest bags from the outer bag to the inner bag
+    fn shortest_path(
+        &self,
+        outer: &str,
+        inner: &str,
+    ) -> Result<Option<ContainmentPath>, CountOverflow> {
+        let (outer, inner) = match (self.bags.get(outer), self.bags.get(inner)) {
+            (Some(outer), Some(inner)) => (outer, inner),
+            _ => return Ok(None),
+        };
+        // Breadth-first search, remembering the bag each bag was first reached from and
+        // the count of that edge
+        let mut parents: Vec<Option<(BagId, usize)>> = vec![None; self.bags.len()];
+        let mut queue = VecDeque::new();
+        queue.push_back(outer);
+        let mut found = outer == inner;
+        while let (false, Some(bag)) = (found, queue.pop_front()) {
+            for &(count, b) in self.inner(bag) {
+                if b != outer && parents[b.index()].is_none() {
+                    parents[b.index()] = Some((bag, count));
+                    found |= b == inner;
+                    queue.push_back(b);
+                }
+            }
+        }
+        if !found {
+            return Ok(None);
+        }
+        let mut bags = Vec::new();
+        let mut bag = inner;
+        while let Some((parent, count)) = parents[bag.index()] {
+            bags.push((bag, count));
+            bag = parent;
+        }
+        bags.push((outer, 1));
+        bags.reverse();
+        self.containment_path(&bags).map(Some)
+    }
+
+    /// Containment path with the most bags from the outer bag to the inner bag
+    fn longest_path(
+        &self,
+        outer: &str,
+        inner: &str,
+    ) -> Result<Option<ContainmentPath>, CountOverflow> {
+        let (outer, inner) = match (self.bags.get(outer), self.bags.get(inner)) {
+            (Some(outer), Some(inner)) => (outer, inner),
+            _ => return Ok(None),
+        };
+        // Length of the longest path to the inner bag and the next bag on it with the count
+        // of that edge, for every bag that leads to the inner bag. The inner bag leads to
+        // itself with length 0.
+        let mut longest: Vec<Option<(usize, BagId, usize)>> = vec![None; self.bags.len()];
+        for bag in self.topological_order(iter::once(outer)) {
+            if bag == inner {
+                longest[bag.index()] = Some((0, inner, 1));
+                continue;
+            }
+            longest[bag.index()] = self
+                .inner(bag)
+                .iter()
+                .filter_map(|&(count, b)| {
+                    longest[b.index()].map(|(len, _next, _count)| (len + 1, b, count))
+                })
+                .max_by_key(|(len, _next, _count)| *len);
+        }
+        if longest[outer.index()].is_none() {
+            return Ok(None);
+        }
+        let mut bags = vec![(outer, 1)];
+        let mut bag = outer;
+        while let Some((len, next, count)) = longest[bag.index()] {
+            if len == 0 {
+                break;
+            }
+            bags.push((next, count));
+            bag = next;
+        }
+        self.containment_path(&bags).map(Some)
+    }
+
+    /// Maximum nesting depth of bags inside every bag reachable from the given bags
+    fn depths_from<I: IntoIterator<Item = BagId>>(&self, bags: I) -> Vec<usize> {
+        let mut depths = vec![0; self.bags.len()];
+        for outer in self.topological_order(bags) {
+            depths[outer.index()] = self
+                .inner(outer)
+                .iter()
+                .map(|(_count, inner)| depths[inner.index()] + 1)
+                .max()
+                .unwrap_or(0);
+        }
+        depths
     }
 
-    fn count_bags(&self, bag: &str) -> usize {
-        self.contains
-            .get(&Bag::from(bag))
-            .map(|bags| {
-                bags.iter()
-                    .map(|(c, b)| c * self.count_bags(&b.0))
-                    .sum::<usize>()
-                    + 1
+    /// Nesting depth of every bag
+    fn depths(&self) -> HashMap<&Bag, usize> {
+        let depths = self.depths_from(self.bags.ids());
+        self.bags
+            .ids()
+            .map(|id| (self.name(id), depths[id.index()]))
+            .collect()
+    }
+
+    /// Maximum nesting depth of bags inside the given bag, which is 0 for an empty bag
+    fn depth(&self, bag: &str) -> usize {
+        match self.bags.get(bag) {
+            Some(id) => self.depths_from(iter::once(id))[id.index()],
+            None => 0,
+        }
+    }
+
+    /// Total number of inner bags inside the outer bag
+    fn count_inside(&self, inner: &str, outer: &str) -> Result<usize, CountOverflow> {
+        let (outer, inner) = match (self.bags.get(outer), self.bags.get(inner)) {
+            (Some(outer), Some(inner)) => (outer, inner),
+            _ => return Ok(0),
+        };
+        let mut totals: Vec<usize> = vec![0; self.bags.len()];
+        for bag in self.topological_order(iter::once(outer)) {
+            totals[bag.index()] = self
+                .inner(bag)
+                .iter()
+                .try_fold(0, |sum: usize, &(count, b)| {
+                    let n = totals[b.index()].checked_add(if b == inner { 1 } else { 0 })?;
+                    count.checked_mul(n)?.checked_add(sum)
+                })
+                .ok_or_else(|| CountOverflow(self.name(bag).clone()))?;
+        }
+        Ok(totals[outer.index()])
+    }
+
+    /// Answer a query as table
+    fn query(&self, query: &Query) -> Result<Table, CountOverflow> {
+        let table = match query {
+            Query::Containers(bag) => {
+                let mut table = Table::new(vec!["Bag", "Count"]);
+                if let Some(inner) = self.bags.get(&bag.0) {
+                    for outer in self.marked(&self.containers(inner, false)) {
+                        let count = self.count_of(outer, inner);
+                        table.push(vec![self.name(outer).to_string(), count.to_string()]);
+                    }
+                }
+                table
+            }
+            Query::Paths(outer, inner) => {
+                let mut table = Table::new(vec!["#", "Multiplicity", "Path"]);
+                for (i, path) in self.paths(&outer.0, &inner.0)?.iter().enumerate() {
+                    table.push(vec![
+                        (i + 1).to_string(),
+                        path.multiplicity.to_string(),
+                        path.to_string(),
+                    ]);
+                }
+                table
+            }
+            Query::ShortestPath(outer, inner) | Query::LongestPath(outer, inner) => {
+                let path = match query {
+                    Query::ShortestPath(..) => self.shortest_path(&outer.0, &inner.0)?,
+                    _ => self.longest_path(&outer.0, &inner.0)?,
+                };
+                let mut table = Table::new(vec!["Length", "Multiplicity", "Path"]);
+                if let Some(path) = path {
+                    table.push(vec![
+                        (path.bags.len() - 1).to_string(),
+                        path.multiplicity.to_string(),
+                        path.to_string(),
+                    ]);
+                }
+                table
+            }
+            Query::Depth(bag) => {
+                let mut table = Table::new(vec!["Bag", "Depth"]);
+                table.push(vec![bag.to_string(), self.depth(&bag.0).to_string()]);
+                table
+            }
+            Query::Depths => {
+                let mut depths: Vec<_> = self.depths().into_iter().collect();
+                depths.sort_by(|(a, da), (b, db)| db.cmp(da).then(a.cmp(b)));
+                let mut table = Table::new(vec!["Bag", "Depth"]);
+                for (bag, depth) in depths {
+                    table.push(vec![bag.to_string(), depth.to_string()]);
+                }
+                table
+            }
+            Query::ContainingBoth(a, b) => {
+                let mut table = Table::new(vec!["Bag"]);
+                if let (Some(a), Some(b)) = (self.bags.get(&a.0), self.bags.get(&b.0)) {
+                    let b = self.containers(b, true);
+                    let mut both = self.containers(a, true);
+                    for (x, y) in both.iter_mut().zip(b) {
+                        *x &= y;
+                    }
+                    for bag in self.marked(&both) {
+                        table.push(vec![self.name(bag)]);
+                    }
+                }
+                table
+            }
+            Query::Total { inner, outer } => {
+                let mut table = Table::new(vec!["Bag", "Inside", "Total"]);
+                let total = self.count_inside(&inner.0, &outer.0)?;
+                table.push(vec![
+                    inner.to_string(),
+                    outer.to_string(),
+                    total.to_string(),
+                ]);
+                table
+            }
+        };
+        Ok(table)
+    }
+
+    /// Rules of all bags, sorted by color
+    fn rules(&self) -> Vec<Rule> {
+        self.sorted_ids()
+            .into_iter()
+            .map(|id| Rule {
+                bag: self.name(id).clone(),
+                contains: self
+                    .inner(id)
+                    .iter()
+                    .map(|&(count, inner)| (count, self.name(inner).clone()))
+                    .collect(),
             })
-            .unwrap_or(0)
+            .collect()
+    }
+
+    /// Write the rules as Graphviz DOT graph, with edges from outer to inner bags labeled
+    /// with counts. The graph may be restricted to the relatives of a query bag, which is
+    /// highlighted.
+    fn write_dot<W: io::Write>(
+        &self,
+        w: &mut W,
+        query: Option<&str>,
+        relatives: Relatives,
+    ) -> io::Result<()> {
+        let query = match query {
+            Some(bag) => Some(
+                self.known(bag)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?,
+            ),
+            None => None,
+        };
+        let mut bags = match (query, relatives) {
+            (Some(query), Relatives::Ancestors) => self.containers(query, true),
+            (Some(query), Relatives::Descendants) => {
+                let mut bags = vec![false; self.bags.len()];
+                for bag in self.topological_order(iter::once(query)) {
+                    bags[bag.index()] = true;
+                }
+                bags
+            }
+            _ => vec![true; self.bags.len()],
+        };
+        if let Some(query) = query {
+            bags[query.index()] = true;
+        }
+
+        writeln!(w, "digraph bags {{")?;
+        let ids = self.marked(&bags);
+        for &bag in &ids {
+            if query == Some(bag) {
+                let id = dot_id(self.name(bag));
+                writeln!(w, "    {} [style=filled, fillcolor=gold];", id)?;
+            } else {
+                writeln!(w, "    {};", dot_id(self.name(bag)))?;
+            }
+        }
+        for &outer in &ids {
+            for &(count, inner) in self.inner(outer) {
+                if bags[inner.index()] {
+                    writeln!(
+                        w,
+                        "    {} -> {} [label=\"{}\"];",
+                        dot_id(self.name(outer)),
+                        dot_id(self.name(inner)),
+                        count
+                    )?;
+                }
+            }
+        }
+        writeln!(w, "}}")
     }
 }
 
 fn main() -> Result<(), Box<dyn error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
     let rules: Vec<Rule> = Input::day(7)?.parsed_lines()?;
-    let rules = RuleSet::from(rules);
+    let rules = RuleSet::try_from(rules)?;
+    for bag in rules.unreachable() {
+        eprintln!("Warning: unreachable bag `{}`", bag);
+    }
+
+    // Normalized rules, sorted by color
+    if args.iter().any(|arg| arg == "--normalize") {
+        print!("{}", rules);
+        return Ok(());
+    }
+
+    // Query subcommand, e.g. `query paths "light red" "shiny gold"`
+    if args.first().map(String::as_str) == Some("query") {
+        let query = Query::from_args(&args[1..])?;
+        print!("{}", rules.query(&query)?);
+        return Ok(());
+    }
+
+    // Graph export: --dot [ancestors|descendants] [BAG]
+    if let Some(pos) = args.iter().position(|arg| arg == "--dot") {
+        let mut dot_args = args[pos + 1..].iter().map(String::as_str);
+        let (relatives, query) = match dot_args.next() {
+            Some("ancestors") => (Relatives::Ancestors, dot_args.next()),
+            Some("descendants") => (Relatives::Descendants, dot_args.next()),
+            query => (Relatives::All, query),
+        };
+        if relatives != Relatives::All && query.is_none() {
+            return Err("Missing bag to restrict graph to".into());
+        }
+        rules.write_dot(&mut io::stdout().lock(), query, relatives)?;
+        return Ok(());
+    }
 
     let count = rules.which_contains("shiny gold", true).len();
     println!(
@@ -131,7 +1209,16 @@ fn main() -> Result<(), Box<dyn error::Error>> {
         count
     );
 
-    let count = rules.count_bags("shiny gold") - 1;
+    // Arbitrary-precision counting for rule sets too deep for usize
+    let count = if args.iter().any(|arg| arg == "--big") {
+        rules
+            .count_bags_inside_as::<BigCount>("shiny gold")?
+            .to_string()
+    } else {
+        rules
+            .count_bags_inside_as::<usize>("shiny gold")?
+            .to_string()
+    };
     println!(
         "Number of individual bags inside single shiny gold bag: {}",
         count
@@ -144,6 +1231,14 @@ fn main() -> Result<(), Box<dyn error::Error>> {
 mod tests {
     use super::*;
 
+    fn rules(lines: &[&str]) -> Vec<Rule> {
+        lines.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    fn rule_set(lines: &[&str]) -> RuleSet {
+        RuleSet::try_from(rules(lines)).unwrap()
+    }
+
     const INPUT_1: [&str; 9] = [
         "light red bags contain 1 bright white bag, 2 muted yellow bags.",
         "dark orange bags contain 3 bright white bags, 4 muted yellow bags.",
@@ -158,11 +1253,7 @@ mod tests {
 
     #[test]
     fn part_1() {
-        let rules: Vec<Rule> = INPUT_1
-            .iter()
-            .map(|s| s.parse())
-            .collect::<Result<_, _>>()
-            .unwrap();
+        let rules = rules(&INPUT_1);
 
         assert_eq!(rules[0].bag, "light red");
         assert_eq!(rules[0].contains[0].0, 1);
@@ -172,7 +1263,8 @@ mod tests {
         assert_eq!(rules[7].bag, "faded blue");
         assert_eq!(rules[7].contains, []);
 
-        let rules = RuleSet::from(rules);
+        let rules = RuleSet::try_from(rules).unwrap();
+        assert_eq!(rules.which_contains("shiny gold", false).len(), 2);
         assert_eq!(rules.which_contains("shiny gold", true).len(), 4);
     }
 
@@ -188,13 +1280,358 @@ mod tests {
 
     #[test]
     fn part_2() {
-        let rules: Vec<Rule> = INPUT_2
-            .iter()
-            .map(|s| s.parse())
-            .collect::<Result<_, _>>()
+        let rules = rule_set(&INPUT_2);
+
+        assert_eq!(
+            rules.count_bags_inside_as::<usize>("shiny gold").ok(),
+            Some(126)
+        );
+    }
+
+    #[test]
+    fn interning() {
+        let rules = rule_set(&INPUT_1);
+
+        assert_eq!(rules.bags.len(), 9);
+        let light_red = rules.bags.get("light red").unwrap();
+        let bright_white = rules.bags.get("bright white").unwrap();
+        assert_eq!(light_red, BagId(0));
+        assert_eq!(bright_white, BagId(1));
+        assert_eq!(rules.name(bright_white), &"bright white");
+        assert_eq!(rules.inner(light_red)[0], (1, bright_white));
+        assert_eq!(rules.contained[bright_white.index()].len(), 2);
+        assert_eq!(rules.bags.get("plaid cyan"), None);
+        let err = rules
+            .count_bags_inside_as::<usize>("plaid cyan")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Unknown bag plaid cyan");
+        assert!(rules.which_contains("plaid cyan", true).is_empty());
+    }
+
+    #[test]
+    fn queries() {
+        let rules = rule_set(&INPUT_1);
+        let query = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+            rules.query(&Query::from_args(&args).unwrap()).unwrap()
+        };
+
+        assert_eq!(
+            query(&["containers", "shiny gold"]).to_string(),
+            "Bag           Count\n\
+             bright white  1\n\
+             muted yellow  2\n"
+        );
+        assert_eq!(
+            query(&["paths", "light red", "shiny gold"]).to_string(),
+            "#  Multiplicity  Path\n\
+             1  1             light red -> bright white -> shiny gold\n\
+             2  4             light red -> muted yellow -> shiny gold\n"
+        );
+        assert_eq!(query(&["paths", "shiny gold", "light red"]).rows.len(), 0);
+        assert_eq!(query(&["depth", "light red"]).rows, [["light red", "4"]]);
+        assert_eq!(query(&["depth", "faded blue"]).rows, [["faded blue", "0"]]);
+        assert_eq!(
+            query(&["both", "bright white", "muted yellow"]).rows,
+            [["dark orange"], ["light red"]]
+        );
+        assert_eq!(
+            query(&["total", "faded blue", "light red"]).rows,
+            [["faded blue", "light red", "83"]]
+        );
+        assert!(Query::from_args(&["depth".to_string()]).is_err());
+    }
+
+    #[test]
+    fn paths() {
+        let rules = rule_set(&INPUT_1);
+
+        let paths = rules.paths("light red", "faded blue").unwrap();
+        let multiplicities: Vec<_> = paths.iter().map(|p| p.multiplicity).collect();
+        assert_eq!(multiplicities, [3, 10, 12, 40, 18]);
+        assert_eq!(multiplicities.iter().sum::<usize>(), 83);
+        assert_eq!(rules.count_inside("faded blue", "light red").ok(), Some(83));
+
+        let shortest = rules.shortest_path("light red", "faded blue").unwrap();
+        assert_eq!(
+            shortest.unwrap().to_string(),
+            "light red -> muted yellow -> faded blue"
+        );
+        let longest = rules
+            .longest_path("light red", "faded blue")
+            .unwrap()
+            .unwrap();
+        assert_eq!(longest.bags.len(), 5);
+        assert!(longest.to_string().starts_with("light red -> "));
+        let path = rules.shortest_path("shiny gold", "shiny gold").unwrap();
+        assert_eq!(path.unwrap().multiplicity, 1);
+        assert_eq!(
+            rules.shortest_path("faded blue", "shiny gold").ok(),
+            Some(None)
+        );
+        assert_eq!(
+            rules.longest_path("faded blue", "shiny gold").ok(),
+            Some(None)
+        );
+
+        let depths = rules.depths();
+        assert_eq!(depths.len(), 9);
+        assert_eq!(depths[&Bag::from("light red")], 4);
+        assert_eq!(depths[&Bag::from("dark orange")], 4);
+        assert_eq!(depths[&Bag::from("shiny gold")], 2);
+        assert_eq!(depths[&Bag::from("dotted black")], 0);
+
+        // A rule listing the same bag twice yields one path per listing
+        let rules = rule_set(&[
+            "x bags contain 1 y bag, 2 y bags.",
+            "y bags contain no other bags.",
+        ]);
+        let paths = rules.paths("x", "y").unwrap();
+        let multiplicities: Vec<_> = paths.iter().map(|p| p.multiplicity).collect();
+        assert_eq!(multiplicities, [1, 2]);
+        assert_eq!(rules.count_inside("y", "x").ok(), Some(3));
+        let longest = rules.longest_path("x", "y").unwrap().unwrap();
+        assert!(multiplicities.contains(&longest.multiplicity));
+    }
+
+    #[test]
+    fn dot() {
+        let rules = rule_set(&INPUT_1);
+
+        let mut dot = Vec::new();
+        rules
+            .write_dot(&mut dot, Some("dark olive"), Relatives::Descendants)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(dot).unwrap(),
+            "digraph bags {\n\
+             \x20   \"dark olive\" [style=filled, fillcolor=gold];\n\
+             \x20   \"dotted black\";\n\
+             \x20   \"faded blue\";\n\
+             \x20   \"dark olive\" -> \"faded blue\" [label=\"3\"];\n\
+             \x20   \"dark olive\" -> \"dotted black\" [label=\"4\"];\n\
+             }\n"
+        );
+
+        let mut dot = Vec::new();
+        rules
+            .write_dot(&mut dot, Some("shiny gold"), Relatives::Ancestors)
             .unwrap();
-        let rules = RuleSet::from(rules);
+        let dot = String::from_utf8(dot).unwrap();
+        assert_eq!(dot.lines().count(), 2 + 5 + 6);
+        assert!(dot.contains("\"muted yellow\" -> \"shiny gold\" [label=\"2\"];"));
+        assert!(!dot.contains("faded blue"));
+
+        let mut dot = Vec::new();
+        rules.write_dot(&mut dot, None, Relatives::All).unwrap();
+        assert_eq!(String::from_utf8(dot).unwrap().lines().count(), 2 + 9 + 13);
+
+        let err = rules
+            .write_dot(&mut Vec::new(), Some("shiny gld"), Relatives::Ancestors)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "Unknown bag shiny gld");
+    }
+
+    #[test]
+    fn overflow() {
+        let mut rules: Vec<Rule> = (0..30)
+            .map(|i| Rule {
+                bag: Bag(format!("bag {}", i).into()),
+                contains: vec![(1000, Bag(format!("bag {}", i + 1).into()))],
+            })
+            .collect();
+        rules.push(Rule {
+            bag: Bag::from("bag 30"),
+            contains: vec![],
+        });
+        let rules = RuleSet::try_from(rules).unwrap();
+
+        let err = rules.count_bags_inside_as::<usize>("bag 0").unwrap_err();
+        assert_eq!(err.to_string(), "Bag count overflow in bag 23 bag");
+        assert_eq!(
+            rules.count_bags_inside_as::<usize>("bag 25").ok(),
+            Some(1_001_001_001_001_000)
+        );
+
+        let count: BigCount = rules.count_bags_inside_as("bag 0").unwrap();
+        assert_eq!(count.to_string(), format!("1{}000", "001".repeat(29)));
+        let count: BigCount = rules.count_bags_inside_as("bag 30").unwrap();
+        assert_eq!(count.to_string(), "0");
+    }
+
+    #[test]
+    fn large_rule_sets() {
+        // Every level contains both bags of the next level, which doubles the number of
+        // paths per level
+        let mut rules: Vec<Rule> = (0..60)
+            .flat_map(|level| {
+                let inner = vec![
+                    (1, Bag(format!("left {}", level + 1).into())),
+                    (1, Bag(format!("right {}", level + 1).into())),
+                ];
+                vec![
+                    Rule {
+                        bag: Bag(format!("left {}", level).into()),
+                        contains: inner.clone(),
+                    },
+                    Rule {
+                        bag: Bag(format!("right {}", level).into()),
+                        contains: inner,
+                    },
+                ]
+            })
+            .collect();
+        rules.push(Rule {
+            bag: Bag::from("left 60"),
+            contains: vec![],
+        });
+        rules.push(Rule {
+            bag: Bag::from("right 60"),
+            contains: vec![],
+        });
+        let rules = RuleSet::try_from(rules).unwrap();
+        assert_eq!(
+            rules.count_bags_inside_as::<usize>("left 0").ok(),
+            Some((1 << 61) - 2)
+        );
+        assert_eq!(rules.which_contains("left 60", true).len(), 120);
+
+        // Deep nesting must not grow the stack
+        let mut rules: Vec<Rule> = (0..100_000)
+            .map(|i| Rule {
+                bag: Bag(format!("bag {}", i).into()),
+                contains: vec![(1, Bag(format!("bag {}", i + 1).into()))],
+            })
+            .collect();
+        rules.push(Rule {
+            bag: Bag::from("bag 100000"),
+            contains: vec![],
+        });
+        let rules = RuleSet::try_from(rules).unwrap();
+        assert_eq!(
+            rules.count_bags_inside_as::<usize>("bag 0").ok(),
+            Some(100_000)
+        );
+        assert_eq!(rules.which_contains("bag 100000", true).len(), 100_000);
+    }
+
+    #[test]
+    fn display() {
+        for s in INPUT_1.iter().chain(&INPUT_2) {
+            assert_eq!(s.parse::<Rule>().unwrap().to_string(), *s);
+        }
+        let rule: Rule = "very dark red bags contain 12 shiny gold bag , 1 blue bags."
+            .parse()
+            .unwrap();
+        assert_eq!(
+            rule.to_string(),
+            "very dark red bags contain 12 shiny gold bags, 1 blue bag."
+        );
+
+        let rules = rule_set(&INPUT_1);
+        let mut sorted = INPUT_1.to_vec();
+        sorted.sort_unstable();
+        assert_eq!(rules.to_string(), sorted.join("\n") + "\n");
+    }
+
+    #[test]
+    fn grammar() {
+        let rule: Rule = "very dark red bags contain 12 shiny gold bags, 1 blue bag.  "
+            .parse()
+            .unwrap();
+        assert_eq!(rule.bag, "very dark red");
+        assert_eq!(rule.contains[0], (12, Bag::from("shiny gold")));
+        assert_eq!(rule.contains[1], (1, Bag::from("blue")));
+
+        let rule: Rule = "a bags contain no other bags.\t".parse().unwrap();
+        assert_eq!(rule.bag, "a");
+        assert_eq!(rule.contains, []);
+
+        let err = "dark red bags contain 1x shiny gold bags."
+            .parse::<Rule>()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid rule: expected count at column 23, found `1x`"
+        );
+        let err = "dark red bags contain 2 shiny gold bags"
+            .parse::<Rule>()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid rule: expected `,` or `.` at column 40, found end of rule"
+        );
+        let err = "bags contain no other bags.".parse::<Rule>().unwrap_err();
+        assert_eq!(err.column, 1);
+        assert_eq!(err.expected, "color");
+        let err = "dark red bags contain no other bags. again"
+            .parse::<Rule>()
+            .unwrap_err();
+        assert_eq!(err.found, "`again`");
+        let err = "dark red bags contain 2 shiny gold."
+            .parse::<Rule>()
+            .unwrap_err();
+        assert_eq!(err.expected, "`bag` or `bags`");
+    }
+
+    #[test]
+    fn cycles() {
+        let rules = rules(&[
+            "light red bags contain 1 bright white bag.",
+            "bright white bags contain 2 shiny gold bags, 1 faded blue bag.",
+            "shiny gold bags contain 3 dark olive bags.",
+            "dark olive bags contain 1 bright white bag.",
+            "faded blue bags contain no other bags.",
+        ]);
+        let err = RuleSet::try_from(rules).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid rule set: \
+             Cyclic rules: bright white -> shiny gold -> dark olive -> bright white"
+        );
+
+        let rules = vec!["faded blue bags contain 2 faded blue bags."
+            .parse()
+            .unwrap()];
+        let err = RuleSet::try_from(rules).unwrap_err();
+        assert_eq!(
+            err.cycle.unwrap().0,
+            [Bag::from("faded blue"), Bag::from("faded blue")]
+        );
+    }
+
+    #[test]
+    fn validation() {
+        let rules = rules(&[
+            "light red bags contain 1 bright white bag, 2 muted yellow bags.",
+            "bright white bags contain 1 shiny gold bag.",
+            "muted yellow bags contain 9 faded blue bags.",
+            "bright white bags contain 2 shiny gold bags.",
+            "shiny gold bags contain no other bags.",
+            "dotted black bags contain no other bags.",
+        ]);
+        let err = RuleSet::try_from(rules).unwrap_err();
+        assert_eq!(
+            err.undefined,
+            [(Bag::from("muted yellow"), Bag::from("faded blue"))]
+        );
+        assert_eq!(err.duplicate, [Bag::from("bright white")]);
+        assert!(err.cycle.is_none());
+        assert_eq!(
+            err.to_string(),
+            "Invalid rule set: undefined `faded blue` in `muted yellow`, \
+             duplicate `bright white`"
+        );
 
-        assert_eq!(rules.count_bags("shiny gold") - 1, 126);
+        let rules = rule_set(&[
+            "light red bags contain 1 shiny gold bag.",
+            "shiny gold bags contain no other bags.",
+            "dotted black bags contain no other bags.",
+        ]);
+        assert_eq!(rules.unreachable(), [&Bag::from("dotted black")]);
+        let rules = rule_set(&["a bags contain no other bags."]);
+        assert_eq!(rules.unreachable(), [&Bag::from("a")]);
+        assert_eq!(rules.count_bags_inside_as::<usize>("a").unwrap(), 0);
     }
 }